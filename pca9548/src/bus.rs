@@ -1,132 +1,513 @@
-use crate::address_from_pins;
+use crate::cache::{IdleCache, NoCache, SelectionCache};
+use crate::error::{Phase, needs_reset};
+use crate::idle::{IdleClock, IdlePolicy, IdleTimeout, IdleTimer, NoTimeout};
 use crate::prelude::PCA9548Error;
-use embedded_hal::i2c::{ErrorType, I2c, Operation, SevenBitAddress};
+use crate::reset::{NoPin, ResetPin};
+use crate::scan::{DeviceMap, probe_all};
+use crate::variant::{Pca9548, Variant};
+use crate::{Channel, MAX_PORTS, address_from_pins};
+use core::marker::PhantomData;
+#[cfg(not(feature = "async"))]
+use embedded_hal::i2c::I2c;
+use embedded_hal::i2c::{Error, ErrorType, Operation, SevenBitAddress};
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::I2c as AsyncI2c;
 
-pub struct MultiplexerBus {
+pub struct MultiplexerBus<C = NoCache, V = Pca9548, T = NoTimeout> {
     address: u8,
+    cache: C,
+    idle: IdlePolicy,
+    timer: T,
+    variant: PhantomData<V>,
 }
 
 impl MultiplexerBus {
     pub fn new() -> Self {
-        Self { address: 0x70 }
+        Self {
+            address: 0x70,
+            cache: NoCache,
+            idle: IdlePolicy::KeepSelected,
+            timer: NoTimeout,
+            variant: PhantomData,
+        }
     }
+}
 
+impl<C, V, T> MultiplexerBus<C, V, T>
+where
+    C: SelectionCache,
+    V: Variant,
+    T: IdleTimer<C>,
+{
     /// Sets the address according to the enabled hardware settings
-    pub fn with_address_pins(mut self, a0: bool, a1: bool, a2: bool) -> Self {
-        self.address = address_from_pins(a0, a1, a2);
-        self
+    ///
+    /// Returns `None` if a pin is set that the variant does not have.
+    pub fn with_address_pins(self, a0: bool, a1: bool, a2: bool) -> Option<Self> {
+        self.with_address(address_from_pins(a0, a1, a2))
     }
 
     /// Sets the address
-    pub fn with_address(mut self, address: u8) -> Self {
+    ///
+    /// Returns `None` if the variant cannot be strapped to `address`, see
+    /// [`Variant::addresses`].
+    pub fn with_address(mut self, address: u8) -> Option<Self> {
+        if !V::addresses().contains(&address) {
+            return None;
+        }
         self.address = address;
+        Some(self)
+    }
+
+    /// Shares the selected channel between all ports so that the select
+    /// write is skipped when the channel has not changed
+    pub fn with_cache<C2>(self, cache: C2) -> MultiplexerBus<C2, V, T>
+    where
+        C2: SelectionCache,
+    {
+        MultiplexerBus {
+            address: self.address,
+            cache,
+            idle: self.idle,
+            timer: self.timer,
+            variant: PhantomData,
+        }
+    }
+
+    /// Drives another member of the PCA954x family, e.g. `with_variant::<Pca9546>()`
+    ///
+    /// Returns `None` if the other part cannot be strapped to the address set
+    /// so far, see [`Variant::addresses`].
+    pub fn with_variant<V2>(self) -> Option<MultiplexerBus<C, V2, T>>
+    where
+        V2: Variant,
+    {
+        if !V2::addresses().contains(&self.address) {
+            return None;
+        }
+        Some(MultiplexerBus {
+            address: self.address,
+            cache: self.cache,
+            idle: self.idle,
+            timer: self.timer,
+            variant: PhantomData,
+        })
+    }
+
+    /// Sets what happens to the selected channel after each transaction
+    pub fn with_idle_policy(mut self, idle: IdlePolicy) -> Self {
+        self.idle = idle;
         self
     }
 
-    pub fn new_port<I2C>(&self, i2c: I2C, port: u8) -> BusPort<I2C> {
-        let id = match port {
-            0 => 0b000_0001,
-            1 => 0b000_0010,
-            2 => 0b000_0100,
-            _ => 0b000_1000,
-        };
+    /// Disables all channels once no port was used for `timeout` milliseconds
+    /// measured with `clock`, checked by `deselect_idle`
+    ///
+    /// The time of the last transaction is kept in the cache, so this needs
+    /// one that records it, see [`IdleCache`].
+    pub fn with_deselect_after_idle<K>(
+        self,
+        timeout: u32,
+        clock: K,
+    ) -> MultiplexerBus<C, V, IdleTimeout<K>>
+    where
+        C: IdleCache,
+        K: IdleClock,
+    {
+        MultiplexerBus {
+            address: self.address,
+            cache: self.cache,
+            idle: self.idle,
+            timer: IdleTimeout::new(timeout, clock),
+            variant: PhantomData,
+        }
+    }
 
+    /// Creates a virtual I²C device talking to the given channel
+    ///
+    /// Transactions fail with [`PCA9548Error::PortError`] if the variant does
+    /// not have that channel.
+    pub fn new_port<I2C>(&self, i2c: I2C, channel: Channel) -> BusPort<'_, I2C, C, T> {
         BusPort {
             bus: i2c,
             address: self.address,
-            port: id,
+            channel,
+            code: V::encode(channel.into()),
+            cache: &self.cache,
+            idle: self.idle,
+            timer: &self.timer,
+            reset_pin: NoPin,
+            delay: (),
         }
     }
+
+    /// Forgets the cached selection, e.g. after the multiplexer was reset
+    pub fn invalidate(&self) {
+        self.cache.set_selected(None);
+    }
 }
 
-pub struct BusPort<I2C> {
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "MultiplexerBus",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<C, V, T> MultiplexerBus<C, V, T>
+where
+    C: SelectionCache,
+    V: Variant,
+    T: IdleTimer<C>,
+{
+    /// Disables all channels if the bus has been idle for longer than the
+    /// timeout set with [`with_deselect_after_idle`](Self::with_deselect_after_idle),
+    /// returning `true` if it did
+    ///
+    /// Call this periodically, e.g. from the main loop or a timer task.
+    pub async fn deselect_idle<I2C>(&self, i2c: &mut I2C) -> Result<bool, PCA9548Error<I2C::Error>>
+    where
+        I2C: AsyncI2c,
+    {
+        if !self.timer.expired(&self.cache) {
+            return Ok(false);
+        }
+
+        self.invalidate();
+        i2c.write(self.address, &[0])
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::Deselect, None, error))?;
+        self.cache.set_selected(Some(0));
+        self.timer.deselected(&self.cache);
+        Ok(true)
+    }
+
+    /// Probes the upstream bus with all channels disabled, then every channel
+    /// in turn, and leaves all channels disabled
+    ///
+    /// Devices answering with all channels disabled are reported as upstream
+    /// devices and are not repeated for each channel.
+    pub async fn scan<I2C>(&self, i2c: &mut I2C) -> Result<DeviceMap, PCA9548Error<I2C::Error>>
+    where
+        I2C: AsyncI2c,
+    {
+        self.invalidate();
+        let result = self.scan_channels(i2c).await;
+        i2c.write(self.address, &[0])
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::Deselect, None, error))?;
+        self.cache.set_selected(Some(0));
+        self.timer.deselected(&self.cache);
+        result
+    }
+
+    async fn scan_channels<I2C>(&self, i2c: &mut I2C) -> Result<DeviceMap, PCA9548Error<I2C::Error>>
+    where
+        I2C: AsyncI2c,
+    {
+        i2c.write(self.address, &[0])
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::Select, None, error))?;
+        let upstream = probe_all(i2c, self.address, 0)
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::Probe, None, error))?;
+
+        let mut channels = [0; MAX_PORTS];
+        for channel in Channel::ALL.into_iter().take(V::CHANNELS as usize) {
+            let code = V::encode(channel.into()).ok_or(PCA9548Error::PortError)?;
+            i2c.write(self.address, &[code])
+                .await
+                .map_err(|error| PCA9548Error::i2c(Phase::Select, Some(channel), error))?;
+            channels[channel.index() as usize] = probe_all(i2c, self.address, upstream)
+                .await
+                .map_err(|error| PCA9548Error::i2c(Phase::Probe, Some(channel), error))?;
+        }
+
+        Ok(DeviceMap::from_probes(upstream, channels))
+    }
+}
+
+impl Default for MultiplexerBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct BusPort<'a, I2C, C = NoCache, T = NoTimeout, RST = NoPin, DELAY = ()> {
     bus: I2C,
     address: u8,
-    port: u8,
+    channel: Channel,
+    /// Control register value selecting the channel, `None` if the variant lacks it
+    code: Option<u8>,
+    cache: &'a C,
+    idle: IdlePolicy,
+    timer: &'a T,
+    reset_pin: RST,
+    delay: DELAY,
 }
 
-impl<I2C> BusPort<I2C>
+impl<'a, I2C, C, T, RST, DELAY> BusPort<'a, I2C, C, T, RST, DELAY>
 where
-    I2C: I2c,
+    C: SelectionCache,
 {
-    fn open_port(&mut self) -> Result<(), MultiplexerError<I2C::Error>> {
-        match self.bus.write(self.address, &[self.port]) {
-            Ok(res) => Ok(res),
-            Err(_) => Err(MultiplexerError::PortError),
+    /// Pulses `pin`, timed with `delay`, to reset the multiplexer when a
+    /// transaction fails with a bus lock-up, then selects the channel again
+    /// and retries the transaction once
+    ///
+    /// Ports sharing the RESET line each need a handle to the pin.
+    pub fn with_reset_pin<RST2, DELAY2>(
+        self,
+        pin: RST2,
+        delay: DELAY2,
+    ) -> BusPort<'a, I2C, C, T, RST2, DELAY2> {
+        BusPort {
+            bus: self.bus,
+            address: self.address,
+            channel: self.channel,
+            code: self.code,
+            cache: self.cache,
+            idle: self.idle,
+            timer: self.timer,
+            reset_pin: pin,
+            delay,
         }
     }
+
+    /// Channel this port talks to
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Forgets the cached selection, e.g. after the multiplexer was reset
+    pub fn invalidate(&self) {
+        self.cache.set_selected(None);
+    }
 }
 
-impl<I2C> ErrorType for BusPort<I2C>
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "BusPort",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, C, T, RST, DELAY> BusPort<'_, I2C, C, T, RST, DELAY>
 where
-    I2C: I2c,
+    I2C: AsyncI2c,
+    C: SelectionCache,
+    T: IdleTimer<C>,
 {
-    type Error = MultiplexerError<I2C::Error>;
+    async fn open_port(&mut self) -> Result<(), PCA9548Error<I2C::Error>> {
+        let code = self.code.ok_or(PCA9548Error::PortError)?;
+        if self.cache.selected() == Some(code) {
+            return Ok(());
+        }
+
+        match self.bus.write(self.address, &[code]).await {
+            Ok(res) => {
+                self.cache.set_selected(Some(code));
+                Ok(res)
+            }
+            Err(error) => {
+                self.cache.set_selected(None);
+                Err(PCA9548Error::i2c(Phase::Select, Some(self.channel), error))
+            }
+        }
+    }
+
+    /// Applies the idle policy after a transaction
+    async fn close_port(&mut self) -> Result<(), PCA9548Error<I2C::Error>> {
+        match self.idle {
+            IdlePolicy::KeepSelected => {
+                self.timer.used(self.cache);
+                Ok(())
+            }
+            IdlePolicy::DeselectAfterUse => self.deselect().await,
+        }
+    }
+
+    async fn deselect(&mut self) -> Result<(), PCA9548Error<I2C::Error>> {
+        if self.cache.selected() == Some(0) {
+            return Ok(());
+        }
+
+        match self.bus.write(self.address, &[0]).await {
+            Ok(()) => {
+                self.cache.set_selected(Some(0));
+                self.timer.deselected(self.cache);
+                Ok(())
+            }
+            Err(error) => {
+                self.cache.set_selected(None);
+                Err(PCA9548Error::i2c(
+                    Phase::Deselect,
+                    Some(self.channel),
+                    error,
+                ))
+            }
+        }
+    }
+
+    /// Disables all channels if the bus has been idle for longer than the
+    /// timeout set with [`MultiplexerBus::with_deselect_after_idle`],
+    /// returning `true` if it did
+    pub async fn deselect_idle(&mut self) -> Result<bool, PCA9548Error<I2C::Error>> {
+        if !self.timer.expired(self.cache) {
+            return Ok(false);
+        }
+
+        self.deselect().await?;
+        Ok(true)
+    }
+
+    fn device_error(&self, error: I2C::Error) -> PCA9548Error<I2C::Error> {
+        PCA9548Error::i2c(Phase::Device, Some(self.channel), error)
+    }
+}
+
+impl<I2C, C, T, RST, DELAY> ErrorType for BusPort<'_, I2C, C, T, RST, DELAY>
+where
+    I2C: ErrorType,
+    C: SelectionCache,
+{
+    type Error = PCA9548Error<I2C::Error>;
 }
 
-impl<I2C> I2c for BusPort<I2C>
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "BusPort",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, C, T, RST, DELAY> BusPort<'_, I2C, C, T, RST, DELAY>
 where
-    I2C: I2c,
+    I2C: AsyncI2c,
+    C: SelectionCache,
+    T: IdleTimer<C>,
+    RST: ResetPin<DELAY>,
 {
-    fn read(&mut self, address: SevenBitAddress, read: &mut [u8]) -> Result<(), Self::Error> {
-        self.open_port()?;
-        self.bus
-            .read(address, read)
-            .map_err(|err| MultiplexerError::I2CError(err))
+    /// Resets the multiplexer after a bus lock-up and selects the channel
+    /// again, returns `true` if the transaction should be retried
+    async fn recover(
+        &mut self,
+        result: &Result<(), I2C::Error>,
+    ) -> Result<bool, PCA9548Error<I2C::Error>> {
+        let Err(error) = result else {
+            return Ok(false);
+        };
+        if !needs_reset(error.kind()) {
+            return Ok(false);
+        }
+        let reset = self
+            .reset_pin
+            .pulse(&mut self.delay)
+            .await
+            .map_err(|_| PCA9548Error::PinError)?;
+        if !reset {
+            return Ok(false);
+        }
+        // Every channel is disabled after a reset
+        self.cache.set_selected(Some(0));
+        self.timer.deselected(self.cache);
+        self.open_port().await?;
+        Ok(true)
     }
+}
 
-    fn write(&mut self, address: SevenBitAddress, write: &[u8]) -> Result<(), Self::Error> {
-        self.open_port()?;
-        self.bus
-            .write(address, write)
-            .map_err(|err| MultiplexerError::I2CError(err))
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "BusPort",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, C, T, RST, DELAY> AsyncI2c for BusPort<'_, I2C, C, T, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    C: SelectionCache,
+    T: IdleTimer<C>,
+    RST: ResetPin<DELAY>,
+{
+    async fn read(&mut self, address: SevenBitAddress, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.open_port().await?;
+        let mut result = self.bus.read(address, read).await;
+        if self.recover(&result).await? {
+            result = self.bus.read(address, read).await;
+        }
+        let result = result.map_err(|error| self.device_error(error));
+        let closed = self.close_port().await;
+        result.and(closed)
+    }
+
+    async fn write(&mut self, address: SevenBitAddress, write: &[u8]) -> Result<(), Self::Error> {
+        self.open_port().await?;
+        let mut result = self.bus.write(address, write).await;
+        if self.recover(&result).await? {
+            result = self.bus.write(address, write).await;
+        }
+        let result = result.map_err(|error| self.device_error(error));
+        let closed = self.close_port().await;
+        result.and(closed)
     }
 
-    fn write_read(
+    async fn write_read(
         &mut self,
         address: SevenBitAddress,
         write: &[u8],
         read: &mut [u8],
     ) -> Result<(), Self::Error> {
-        self.open_port()?;
-        self.bus
-            .write_read(address, write, read)
-            .map_err(|err| MultiplexerError::I2CError(err))
+        self.open_port().await?;
+        let mut result = self.bus.write_read(address, write, read).await;
+        if self.recover(&result).await? {
+            result = self.bus.write_read(address, write, read).await;
+        }
+        let result = result.map_err(|error| self.device_error(error));
+        let closed = self.close_port().await;
+        result.and(closed)
     }
 
-    fn transaction(
+    async fn transaction(
         &mut self,
         address: SevenBitAddress,
         operations: &mut [Operation<'_>],
     ) -> Result<(), Self::Error> {
-        self.open_port()?;
-        self.bus
-            .transaction(address, operations)
-            .map_err(|err| MultiplexerError::I2CError(err))
+        self.open_port().await?;
+        let mut result = self.bus.transaction(address, operations).await;
+        if self.recover(&result).await? {
+            result = self.bus.transaction(address, operations).await;
+        }
+        let result = result.map_err(|error| self.device_error(error));
+        let closed = self.close_port().await;
+        result.and(closed)
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "async")))]
 mod test {
     extern crate alloc;
     use crate::prelude::*;
     use alloc::vec;
     use core::cell::RefCell;
-    use embedded_hal::i2c::I2c;
+    use embedded_hal::i2c::{ErrorKind, I2c};
     use embedded_hal_bus::i2c::RefCellDevice;
     use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use rstest::*;
 
     #[test]
     fn multi_port_write() {
-        let multiplexer_addr = 0x01;
+        let multiplexer_addr = 0x71;
         let component_addr = 0x02;
 
         // Use port 1, 3, 2, 4 in that order
-        let ports = vec![
-            (0, 0b000_0001),
-            (2, 0b000_0100),
-            (1, 0b000_0010),
-            (3, 0b000_1000),
+        let ports = [
+            (Channel::CH0, 0b000_0001),
+            (Channel::CH2, 0b000_0100),
+            (Channel::CH1, 0b000_0010),
+            (Channel::CH3, 0b000_1000),
         ];
 
         let expectations = [
@@ -141,7 +522,9 @@ mod test {
         ];
 
         let i2c = RefCell::new(Mock::new(&expectations));
-        let multiplexer = MultiplexerBus::new().with_address(multiplexer_addr);
+        let multiplexer = MultiplexerBus::new()
+            .with_address(multiplexer_addr)
+            .unwrap();
 
         {
             let mut multiplexed_i2c_a = multiplexer.new_port(RefCellDevice::new(&i2c), ports[0].0);
@@ -172,15 +555,15 @@ mod test {
 
     #[test]
     fn multi_port_read() {
-        let multiplexer_addr = 0x01;
+        let multiplexer_addr = 0x71;
         let component_addr = 0x02;
 
         // Use port 1, 3, 2, 4 in that order
-        let ports = vec![
-            (0, 0b000_0001),
-            (2, 0b000_0100),
-            (1, 0b000_0010),
-            (3, 0b000_1000),
+        let ports = [
+            (Channel::CH0, 0b000_0001),
+            (Channel::CH2, 0b000_0100),
+            (Channel::CH1, 0b000_0010),
+            (Channel::CH3, 0b000_1000),
         ];
 
         let expectations = [
@@ -195,7 +578,9 @@ mod test {
         ];
 
         let i2c = RefCell::new(Mock::new(&expectations));
-        let multiplexer = MultiplexerBus::new().with_address(multiplexer_addr);
+        let multiplexer = MultiplexerBus::new()
+            .with_address(multiplexer_addr)
+            .unwrap();
 
         {
             let mut multiplexed_i2c_a = multiplexer.new_port(RefCellDevice::new(&i2c), ports[0].0);
@@ -225,15 +610,15 @@ mod test {
 
     #[test]
     fn multi_port_read_write() {
-        let multiplexer_addr = 0x01;
+        let multiplexer_addr = 0x71;
         let component_addr = 0x02;
 
         // Use port 1, 3, 2, 4 in that order
-        let ports = vec![
-            (0, 0b000_0001),
-            (2, 0b000_0100),
-            (1, 0b000_0010),
-            (3, 0b000_1000),
+        let ports = [
+            (Channel::CH0, 0b000_0001),
+            (Channel::CH2, 0b000_0100),
+            (Channel::CH1, 0b000_0010),
+            (Channel::CH3, 0b000_1000),
         ];
 
         let expectations = [
@@ -248,7 +633,9 @@ mod test {
         ];
 
         let i2c = RefCell::new(Mock::new(&expectations));
-        let multiplexer = MultiplexerBus::new().with_address(multiplexer_addr);
+        let multiplexer = MultiplexerBus::new()
+            .with_address(multiplexer_addr)
+            .unwrap();
 
         {
             let mut multiplexed_i2c_a = multiplexer.new_port(RefCellDevice::new(&i2c), ports[0].0);
@@ -291,4 +678,461 @@ mod test {
 
         i2c.into_inner().done();
     }
+
+    #[rstest]
+    #[case(Channel::CH0, 0b0000_0001)]
+    #[case(Channel::CH1, 0b0000_0010)]
+    #[case(Channel::CH2, 0b0000_0100)]
+    #[case(Channel::CH3, 0b0000_1000)]
+    #[case(Channel::CH4, 0b0001_0000)]
+    #[case(Channel::CH5, 0b0010_0000)]
+    #[case(Channel::CH6, 0b0100_0000)]
+    #[case(Channel::CH7, 0b1000_0000)]
+    fn every_channel(#[case] channel: Channel, #[case] code: u8) {
+        let multiplexer_addr = 0x70;
+        let component_addr = 0x04;
+
+        let expectations = [
+            Transaction::write(multiplexer_addr, vec![code]),
+            Transaction::write(component_addr, vec![0x01]),
+            Transaction::write(multiplexer_addr, vec![code]),
+            Transaction::read(component_addr, vec![0x02]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new();
+
+        {
+            let mut port = multiplexer.new_port(&mut i2c, channel);
+            assert_eq!(port.channel(), channel);
+            assert!(port.write(component_addr, &[0x01]).is_ok());
+            let mut data = [0];
+            assert!(port.read(component_addr, &mut data).is_ok());
+            assert_eq!(data, [0x02]);
+        }
+
+        i2c.done();
+    }
+
+    #[test]
+    fn cached_selection() {
+        let multiplexer_addr = 0x70;
+        let component_addr = 0x04;
+
+        let expectations = [
+            Transaction::write(multiplexer_addr, vec![0b0000_0100]),
+            Transaction::write(component_addr, vec![0x01]),
+            Transaction::read(component_addr, vec![0x02]),
+            Transaction::write_read(component_addr, vec![0x03], vec![0x04]),
+            Transaction::write(multiplexer_addr, vec![0b0100_0000]),
+            Transaction::write(component_addr, vec![0x05]),
+            Transaction::write(multiplexer_addr, vec![0b0000_0100]),
+            Transaction::write(component_addr, vec![0x06]),
+            Transaction::write(multiplexer_addr, vec![0b0000_0100]),
+            Transaction::write(component_addr, vec![0x07]),
+        ];
+
+        let i2c = RefCell::new(Mock::new(&expectations));
+        let multiplexer = MultiplexerBus::new().with_cache(RefCellCache::new());
+
+        {
+            let mut port_a = multiplexer.new_port(RefCellDevice::new(&i2c), Channel::CH2);
+            let mut port_b = multiplexer.new_port(RefCellDevice::new(&i2c), Channel::CH6);
+
+            let mut data = [0];
+            assert!(port_a.write(component_addr, &[0x01]).is_ok());
+            assert!(port_a.read(component_addr, &mut data).is_ok());
+            assert!(
+                port_a
+                    .write_read(component_addr, &[0x03], &mut data)
+                    .is_ok()
+            );
+            assert!(port_b.write(component_addr, &[0x05]).is_ok());
+            assert!(port_a.write(component_addr, &[0x06]).is_ok());
+            multiplexer.invalidate();
+            assert!(port_a.write(component_addr, &[0x07]).is_ok());
+        }
+
+        i2c.into_inner().done();
+    }
+
+    #[test]
+    fn scan_channels() {
+        use embedded_hal::i2c::NoAcknowledgeSource;
+
+        let multiplexer_addr = 0x71;
+        let nack = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+
+        let mut expectations = vec![Transaction::write(multiplexer_addr, vec![0])];
+        for address in 0x08..=0x77 {
+            if address != multiplexer_addr {
+                expectations.push(Transaction::read(address, vec![0]).with_error(nack));
+            }
+        }
+        for channel in Channel::ALL {
+            expectations.push(Transaction::write(multiplexer_addr, vec![channel.mask()]));
+            for address in 0x08..=0x77 {
+                if address == multiplexer_addr {
+                    continue;
+                }
+                let transaction = Transaction::read(address, vec![0]);
+                if channel == Channel::CH5 && address == 0x40 {
+                    expectations.push(transaction);
+                } else {
+                    expectations.push(transaction.with_error(nack));
+                }
+            }
+        }
+        expectations.push(Transaction::write(multiplexer_addr, vec![0]));
+        expectations.push(Transaction::write(multiplexer_addr, vec![0b0010_0000]));
+        expectations.push(Transaction::write(0x40, vec![0x01]));
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new()
+            .with_address(multiplexer_addr)
+            .unwrap()
+            .with_cache(RefCellCache::new());
+
+        let map = multiplexer.scan(&mut i2c).unwrap();
+        assert_eq!(map.channels_with(0x40), PortSet::from(Channel::CH5));
+        assert_eq!(map.upstream().count(), 0);
+
+        {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH5);
+            assert!(port.write(0x40, &[0x01]).is_ok());
+        }
+
+        i2c.done();
+    }
+
+    #[test]
+    fn encoded_channels() {
+        use crate::variant::{Pca9544, Pca9546};
+
+        let expectations = [
+            Transaction::write(0x70, vec![0b0000_0110]),
+            Transaction::write(0x04, vec![0x01]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new().with_variant::<Pca9544>().unwrap();
+        {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH2);
+            assert!(port.write(0x04, &[0x01]).is_ok());
+        }
+
+        let multiplexer = MultiplexerBus::new().with_variant::<Pca9546>().unwrap();
+        {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH4);
+            assert_eq!(port.write(0x04, &[0x01]), Err(PCA9548Error::PortError));
+        }
+
+        i2c.done();
+    }
+
+    #[test]
+    fn rejects_addresses_the_variant_lacks() {
+        use crate::variant::Pca9543;
+
+        assert!(MultiplexerBus::new().with_address(0x08).is_none());
+        let bus = MultiplexerBus::new().with_variant::<Pca9543>().unwrap();
+        assert!(bus.with_address_pins(true, false, true).is_none());
+        let bus = MultiplexerBus::new().with_address(0x76).unwrap();
+        assert!(bus.with_variant::<Pca9543>().is_none());
+    }
+
+    #[test]
+    fn failed_select_invalidates_cache() {
+        let multiplexer_addr = 0x70;
+        let component_addr = 0x04;
+
+        let expectations = [
+            Transaction::write(multiplexer_addr, vec![0b0000_0001]).with_error(ErrorKind::Other),
+            Transaction::write(multiplexer_addr, vec![0b0000_0001]),
+            Transaction::write(component_addr, vec![0x01]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new().with_cache(RefCellCache::new());
+
+        {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH0);
+            assert_eq!(
+                port.write(component_addr, &[0x01]),
+                Err(PCA9548Error::I2CError {
+                    mux: None,
+                    channel: Some(Channel::CH0),
+                    phase: Phase::Select,
+                    error: ErrorKind::Other
+                })
+            );
+            assert!(port.write(component_addr, &[0x01]).is_ok());
+        }
+
+        i2c.done();
+    }
+
+    #[test]
+    fn deselect_after_use() {
+        let expectations = [
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::write(0x70, vec![0b0000_0000]),
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write_read(0x04, vec![0x02], vec![0x03]),
+            Transaction::write(0x70, vec![0b0000_0000]),
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::read(0x04, vec![0x04]).with_error(ErrorKind::Other),
+            Transaction::write(0x70, vec![0b0000_0000]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new()
+            .with_cache(RefCellCache::new())
+            .with_idle_policy(IdlePolicy::DeselectAfterUse);
+
+        {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH1);
+            let mut data = [0];
+            port.write(0x04, &[0x01]).unwrap();
+            port.write_read(0x04, &[0x02], &mut data).unwrap();
+            assert_eq!(data, [0x03]);
+            // The channel is deselected after a failed transaction too
+            assert!(matches!(
+                port.read(0x04, &mut data),
+                Err(PCA9548Error::I2CError {
+                    phase: Phase::Device,
+                    ..
+                })
+            ));
+        }
+
+        i2c.done();
+    }
+
+    #[test]
+    fn deselect_after_idle() {
+        struct TestClock(core::cell::Cell<u32>);
+
+        impl IdleClock for TestClock {
+            fn now_ms(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        let expectations = [
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::write(0x04, vec![0x02]),
+            Transaction::write(0x70, vec![0b0000_0000]),
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::write(0x04, vec![0x03]),
+            Transaction::write(0x70, vec![0b0000_0000]),
+        ];
+
+        let clock = TestClock(core::cell::Cell::new(0));
+        let i2c = RefCell::new(Mock::new(&expectations));
+        let multiplexer = MultiplexerBus::new()
+            .with_cache(RefCellCache::new())
+            .with_deselect_after_idle(50, &clock);
+
+        {
+            let mut port = multiplexer.new_port(RefCellDevice::new(&i2c), Channel::CH0);
+            port.write(0x04, &[0x01]).unwrap();
+            clock.0.set(40);
+            port.write(0x04, &[0x02]).unwrap();
+            clock.0.set(80);
+            assert!(!port.deselect_idle().unwrap());
+            clock.0.set(90);
+            assert!(port.deselect_idle().unwrap());
+            assert!(!port.deselect_idle().unwrap());
+
+            port.write(0x04, &[0x03]).unwrap();
+        }
+        clock.0.set(140);
+        let mut bus = RefCellDevice::new(&i2c);
+        assert!(multiplexer.deselect_idle(&mut bus).unwrap());
+
+        i2c.into_inner().done();
+    }
+
+    #[test]
+    fn resets_after_lock_up() {
+        use embedded_hal_mock::eh1::delay::{CheckedDelay, Transaction as DelayTransaction};
+        use embedded_hal_mock::eh1::digital::{
+            Mock as PinMock, State, Transaction as PinTransaction,
+        };
+
+        let expectations = [
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]).with_error(ErrorKind::Bus),
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]),
+            // Other errors are not retried
+            Transaction::write(0x04, vec![0x02]).with_error(ErrorKind::Other),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let mut pin = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let mut delay = CheckedDelay::new(&[
+            DelayTransaction::delay_ns(6),
+            DelayTransaction::delay_ns(500),
+        ]);
+        let multiplexer = MultiplexerBus::new().with_cache(RefCellCache::new());
+
+        {
+            let mut port = multiplexer
+                .new_port(&mut i2c, Channel::CH1)
+                .with_reset_pin(&mut pin, &mut delay);
+            port.write(0x04, &[0x01]).unwrap();
+            assert!(matches!(
+                port.write(0x04, &[0x02]),
+                Err(PCA9548Error::I2CError {
+                    phase: Phase::Device,
+                    ..
+                })
+            ));
+        }
+
+        i2c.done();
+        pin.done();
+        delay.done();
+    }
+
+    #[test]
+    fn device_errors_name_the_channel() {
+        use embedded_hal::i2c::{Error, NoAcknowledgeSource};
+
+        let nack = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+        let expectations = [
+            Transaction::write(0x70, vec![0b0001_0000]),
+            Transaction::write(0x04, vec![0x01]).with_error(nack),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new();
+
+        {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH4);
+            let error = port.write(0x04, &[0x01]).unwrap_err();
+            assert_eq!(
+                error,
+                PCA9548Error::I2CError {
+                    mux: None,
+                    channel: Some(Channel::CH4),
+                    phase: Phase::Device,
+                    error: nack
+                }
+            );
+            assert_eq!(error.channel(), Some(Channel::CH4));
+            assert_eq!(error.kind(), nack);
+        }
+
+        i2c.done();
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test_async {
+    extern crate alloc;
+    use crate::prelude::*;
+    use alloc::vec;
+    use embassy_futures::block_on;
+    use embedded_hal_async::i2c::I2c;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use rstest::*;
+
+    #[rstest]
+    #[case(Channel::CH0, 0b0000_0001)]
+    #[case(Channel::CH1, 0b0000_0010)]
+    #[case(Channel::CH2, 0b0000_0100)]
+    #[case(Channel::CH3, 0b0000_1000)]
+    #[case(Channel::CH4, 0b0001_0000)]
+    #[case(Channel::CH5, 0b0010_0000)]
+    #[case(Channel::CH6, 0b0100_0000)]
+    #[case(Channel::CH7, 0b1000_0000)]
+    fn every_channel(#[case] channel: Channel, #[case] code: u8) {
+        let multiplexer_addr = 0x70;
+        let component_addr = 0x04;
+
+        let expectations = [
+            Transaction::write(multiplexer_addr, vec![code]),
+            Transaction::write(component_addr, vec![0x01]),
+            Transaction::write(multiplexer_addr, vec![code]),
+            Transaction::read(component_addr, vec![0x02]),
+            Transaction::write(multiplexer_addr, vec![code]),
+            Transaction::write_read(component_addr, vec![0x03], vec![0x04]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new();
+
+        block_on(async {
+            let mut port = multiplexer.new_port(&mut i2c, channel);
+            assert!(port.write(component_addr, &[0x01]).await.is_ok());
+            let mut data = [0];
+            assert!(port.read(component_addr, &mut data).await.is_ok());
+            assert_eq!(data, [0x02]);
+            assert!(
+                port.write_read(component_addr, &[0x03], &mut data)
+                    .await
+                    .is_ok()
+            );
+            assert_eq!(data, [0x04]);
+        });
+
+        i2c.done();
+    }
+
+    #[test]
+    fn cached_selection() {
+        let multiplexer_addr = 0x70;
+        let component_addr = 0x04;
+
+        let expectations = [
+            Transaction::write(multiplexer_addr, vec![0b0000_1000]),
+            Transaction::write(component_addr, vec![0x01]),
+            Transaction::write(component_addr, vec![0x02]),
+            Transaction::write(multiplexer_addr, vec![0b0001_0000]),
+            Transaction::read(component_addr, vec![0x03]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new().with_cache(RefCellCache::new());
+
+        block_on(async {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH3);
+            assert!(port.write(component_addr, &[0x01]).await.is_ok());
+            assert!(port.write(component_addr, &[0x02]).await.is_ok());
+
+            let mut port = multiplexer.new_port(port.bus, Channel::CH4);
+            let mut data = [0];
+            assert!(port.read(component_addr, &mut data).await.is_ok());
+            assert_eq!(data, [0x03]);
+        });
+
+        i2c.done();
+    }
+
+    #[test]
+    fn deselect_after_use() {
+        let expectations = [
+            Transaction::write(0x70, vec![0b1000_0000]),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::write(0x70, vec![0b0000_0000]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let multiplexer = MultiplexerBus::new().with_idle_policy(IdlePolicy::DeselectAfterUse);
+
+        block_on(async {
+            let mut port = multiplexer.new_port(&mut i2c, Channel::CH7);
+            assert!(port.write(0x04, &[0x01]).await.is_ok());
+        });
+
+        i2c.done();
+    }
 }
@@ -0,0 +1,217 @@
+//! Shared record of the channel currently selected on a multiplexer.
+//!
+//! All [`BusPort`](crate::bus::BusPort)s created from the same
+//! [`MultiplexerBus`](crate::bus::MultiplexerBus) see the same cache, so the
+//! channel select write is only sent when the active channel changes.
+//! Caches other than [`NoCache`] also keep the time of the last transaction
+//! for [`MultiplexerBus::with_deselect_after_idle`].
+//!
+//! [`MultiplexerBus::with_deselect_after_idle`]: crate::bus::MultiplexerBus::with_deselect_after_idle
+
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::sync::PoisonError;
+
+/// Storage for the control byte last written to the multiplexer.
+pub trait SelectionCache {
+    /// Control byte last written to the multiplexer, if known
+    fn selected(&self) -> Option<u8>;
+
+    /// Records the control byte written to the multiplexer, `None` if unknown
+    fn set_selected(&self, code: Option<u8>);
+}
+
+/// Cache that also records when the bus was last used
+pub trait IdleCache: SelectionCache {
+    /// Time of the last transaction that left a channel selected, `None` once
+    /// all channels are disabled
+    fn last_used(&self) -> Option<u32>;
+
+    /// Records the time of the last transaction, `None` once all channels are
+    /// disabled
+    fn set_last_used(&self, time: Option<u32>);
+}
+
+/// Cache that never remembers the selection, every transaction selects the channel
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoCache;
+
+impl SelectionCache for NoCache {
+    fn selected(&self) -> Option<u8> {
+        None
+    }
+
+    fn set_selected(&self, _code: Option<u8>) {}
+}
+
+/// Cache for ports used from a single thread or task
+#[derive(Debug, Default)]
+pub struct RefCellCache {
+    selected: RefCell<Option<u8>>,
+    last_used: RefCell<Option<u32>>,
+}
+
+impl RefCellCache {
+    pub const fn new() -> Self {
+        Self {
+            selected: RefCell::new(None),
+            last_used: RefCell::new(None),
+        }
+    }
+}
+
+impl SelectionCache for RefCellCache {
+    fn selected(&self) -> Option<u8> {
+        *self.selected.borrow()
+    }
+
+    fn set_selected(&self, code: Option<u8>) {
+        *self.selected.borrow_mut() = code;
+    }
+}
+
+impl IdleCache for RefCellCache {
+    fn last_used(&self) -> Option<u32> {
+        *self.last_used.borrow()
+    }
+
+    fn set_last_used(&self, time: Option<u32>) {
+        *self.last_used.borrow_mut() = time;
+    }
+}
+
+/// Cache shared between interrupt contexts through a critical section
+#[cfg(feature = "critical-section")]
+#[derive(Debug)]
+pub struct CriticalSectionCache {
+    selected: critical_section::Mutex<core::cell::Cell<Option<u8>>>,
+    last_used: critical_section::Mutex<core::cell::Cell<Option<u32>>>,
+}
+
+#[cfg(feature = "critical-section")]
+impl CriticalSectionCache {
+    pub const fn new() -> Self {
+        Self {
+            selected: critical_section::Mutex::new(core::cell::Cell::new(None)),
+            last_used: critical_section::Mutex::new(core::cell::Cell::new(None)),
+        }
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl Default for CriticalSectionCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl SelectionCache for CriticalSectionCache {
+    fn selected(&self) -> Option<u8> {
+        critical_section::with(|cs| self.selected.borrow(cs).get())
+    }
+
+    fn set_selected(&self, code: Option<u8>) {
+        critical_section::with(|cs| self.selected.borrow(cs).set(code))
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl IdleCache for CriticalSectionCache {
+    fn last_used(&self) -> Option<u32> {
+        critical_section::with(|cs| self.last_used.borrow(cs).get())
+    }
+
+    fn set_last_used(&self, time: Option<u32>) {
+        critical_section::with(|cs| self.last_used.borrow(cs).set(time))
+    }
+}
+
+/// Cache shared between threads through a [`std::sync::Mutex`]
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct MutexCache {
+    selected: std::sync::Mutex<Option<u8>>,
+    last_used: std::sync::Mutex<Option<u32>>,
+}
+
+#[cfg(feature = "std")]
+impl MutexCache {
+    pub const fn new() -> Self {
+        Self {
+            selected: std::sync::Mutex::new(None),
+            last_used: std::sync::Mutex::new(None),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl SelectionCache for MutexCache {
+    fn selected(&self) -> Option<u8> {
+        *self.selected.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_selected(&self, code: Option<u8>) {
+        *self.selected.lock().unwrap_or_else(PoisonError::into_inner) = code;
+    }
+}
+
+#[cfg(feature = "std")]
+impl IdleCache for MutexCache {
+    fn last_used(&self) -> Option<u32> {
+        *self
+            .last_used
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_last_used(&self, time: Option<u32>) {
+        *self
+            .last_used
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = time;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(cache: &impl IdleCache) {
+        assert_eq!(cache.selected(), None);
+        cache.set_selected(Some(0b0010_0000));
+        assert_eq!(cache.selected(), Some(0b0010_0000));
+        cache.set_selected(None);
+        assert_eq!(cache.selected(), None);
+
+        assert_eq!(cache.last_used(), None);
+        cache.set_last_used(Some(1234));
+        assert_eq!(cache.last_used(), Some(1234));
+        assert_eq!(cache.selected(), None);
+        cache.set_last_used(None);
+        assert_eq!(cache.last_used(), None);
+    }
+
+    #[test]
+    fn no_cache() {
+        NoCache.set_selected(Some(0b0000_0001));
+        assert_eq!(NoCache.selected(), None);
+    }
+
+    #[test]
+    fn ref_cell_cache() {
+        round_trip(&RefCellCache::new());
+    }
+
+    #[test]
+    #[cfg(feature = "critical-section")]
+    fn critical_section_cache() {
+        round_trip(&CriticalSectionCache::new());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn mutex_cache() {
+        round_trip(&MutexCache::new());
+    }
+}
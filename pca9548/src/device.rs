@@ -1,10 +1,23 @@
 use crate::{
-    MAX_PORTS, PCA9548,
-    error::{PCA9548Error, Result},
+    Channel, MAX_PORTS, PCA9548, PortSet,
+    error::{PCA9548Error, Phase, Result, needs_reset},
+    reset::ResetPin,
+    scan::{ConflictPolicy, DeviceMap, probe_all},
+    variant::{Interrupts, Variant},
 };
 #[cfg(not(feature = "async"))]
+use embedded_hal::delay::DelayNs;
+#[cfg(not(feature = "async"))]
+use embedded_hal::digital::InputPin;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::Error;
+#[cfg(not(feature = "async"))]
 use embedded_hal::i2c::I2c;
 #[cfg(feature = "async")]
+use embedded_hal_async::delay::DelayNs as AsyncDelayNs;
+#[cfg(feature = "async")]
+use embedded_hal_async::digital::Wait;
+#[cfg(feature = "async")]
 use embedded_hal_async::i2c::I2c as AsyncI2c;
 
 #[maybe_async_cfg::maybe(
@@ -15,72 +28,351 @@ use embedded_hal_async::i2c::I2c as AsyncI2c;
     ),
     async(feature = "async", keep_self)
 )]
-impl<I2C> PCA9548<I2C>
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
 where
     I2C: AsyncI2c,
+    V: Variant,
 {
     /// Disables all ports
     pub async fn with_ports_disabled(self) -> Result<Self, I2C::Error> {
-        self.with_ports([false; MAX_PORTS]).await
+        self.with_ports(PortSet::default()).await
     }
 
     /// Disables all ports
     pub async fn set_ports_disabled(mut self) -> Result<(), I2C::Error> {
-        self.set_ports([false; MAX_PORTS]).await
+        self.set_ports(PortSet::default()).await
     }
 
     /// Enables all ports
     pub async fn with_ports_enabled(self) -> Result<Self, I2C::Error> {
-        self.with_ports([true; MAX_PORTS]).await
+        self.with_ports(V::all_ports()).await
     }
 
     /// Enables all ports
     pub async fn set_ports_enabled(mut self) -> Result<(), I2C::Error> {
-        self.set_ports([true; MAX_PORTS]).await
+        self.set_ports(V::all_ports()).await
     }
 
     /// Enables / Disables the selected port
-    pub async fn set_port(&mut self, port: u8, state: impl Into<bool>) -> Result<(), I2C::Error> {
-        if port >= MAX_PORTS as u8 {
-            return Err(PCA9548Error::PortError);
-        }
-
-        self.state[port as usize] = state.into();
+    ///
+    /// Multiplexers with an encoded channel field (PCA9544, PCA9547) only
+    /// accept a single enabled port.
+    pub async fn set_port(
+        &mut self,
+        channel: Channel,
+        state: impl Into<bool>,
+    ) -> Result<(), I2C::Error> {
+        let mut ports = self.state;
+        ports.set(channel, state.into());
 
-        let code = Self::port_code(self.state);
-
-        self.i2c_write(&[code]).await
+        self.check_conflicts(ports)?;
+        self.write_ports(ports, Some(channel)).await
     }
 
     /// Sets the selected port
-    pub async fn with_port(mut self, port: u8, state: impl Into<bool>) -> Result<Self, I2C::Error> {
-        self.set_port(port, state.into()).await?;
+    pub async fn with_port(
+        mut self,
+        channel: Channel,
+        state: impl Into<bool>,
+    ) -> Result<Self, I2C::Error> {
+        self.set_port(channel, state.into()).await?;
         Ok(self)
     }
 
     /// Enables / Disables the selected ports
-    pub async fn set_ports(&mut self, ports: [bool; MAX_PORTS]) -> Result<(), I2C::Error> {
-        let code = Self::port_code(ports);
-        self.i2c_write(&[code]).await
+    pub async fn set_ports(&mut self, ports: impl Into<PortSet>) -> Result<(), I2C::Error> {
+        let ports = ports.into();
+        self.check_conflicts(ports)?;
+        self.write_ports(ports, None).await
     }
 
     /// Enables / Disables the selected ports
-    pub async fn with_ports(mut self, ports: [bool; MAX_PORTS]) -> Result<Self, I2C::Error> {
+    pub async fn with_ports(mut self, ports: impl Into<PortSet>) -> Result<Self, I2C::Error> {
         self.set_ports(ports).await?;
         Ok(self)
     }
 
-    async fn i2c_write(&mut self, bytes: &[u8]) -> Result<(), I2C::Error> {
+    /// Reads the control register and updates the cached port selection
+    pub async fn read_ports(&mut self) -> Result<PortSet, I2C::Error> {
+        let ports = V::decode(self.i2c_read(None).await?);
+        self.state = ports;
+        Ok(ports)
+    }
+
+    /// Reads the control register and returns `true` if the cached port
+    /// selection was out of sync with the device
+    pub async fn sync_state(&mut self) -> Result<bool, I2C::Error> {
+        let cached = self.state;
+        Ok(self.read_ports().await? != cached)
+    }
+
+    /// Probes the upstream bus with all ports disabled, then every port in
+    /// turn, and restores the previous port selection
+    ///
+    /// Devices answering with all ports disabled are reported as upstream
+    /// devices and are not repeated for each port. A failed scan is reported
+    /// over a failure to restore the selection.
+    pub async fn scan(&mut self) -> Result<DeviceMap, I2C::Error> {
+        let previous = self.state;
+        let result = self.scan_ports().await;
+        let restored = self.write_ports(previous, None).await;
+        let devices = result?;
+        restored?;
+        Ok(devices)
+    }
+
+    /// Scans every port like [`scan`](Self::scan) and registers the devices
+    /// found for conflict checks
+    pub async fn scan_and_register(&mut self) -> Result<DeviceMap, I2C::Error> {
+        let devices = self.scan().await?;
+        self.devices = Some(devices);
+        Ok(devices)
+    }
+
+    /// Writes `bytes` to `address` on every channel in `channels` at once and
+    /// restores the previous port selection, also when selecting the channels
+    /// or the write fails
+    ///
+    /// Identical devices on several channels receive the same frame in a
+    /// single transaction, so registered devices are not checked for address
+    /// conflicts. Only switches can enable several channels, the
+    /// multiplexers (PCA9544, PCA9547) fail with [`PCA9548Error::PortError`]
+    /// unless a single channel is given.
+    pub async fn broadcast_write(
+        &mut self,
+        channels: impl Into<PortSet>,
+        address: u8,
+        bytes: &[u8],
+    ) -> Result<(), I2C::Error> {
+        let previous = self.state;
+        let mut result = self.write_ports(channels.into(), None).await;
+        if result.is_ok() {
+            result = self
+                .i2c
+                .write(address, bytes)
+                .await
+                .map_err(|error| PCA9548Error::i2c(Phase::Device, None, error));
+        }
+        // A select that failed verification may still have changed the ports
+        if self.state != previous {
+            self.write_ports(previous, None).await?;
+        }
+        result
+    }
+
+    async fn scan_ports(&mut self) -> Result<DeviceMap, I2C::Error> {
+        self.write_ports(PortSet::default(), None).await?;
+        let upstream = probe_all(&mut self.i2c, self.address, 0)
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::Probe, None, error))?;
+
+        let mut channels = [0; MAX_PORTS];
+        for channel in Channel::ALL.into_iter().take(V::CHANNELS as usize) {
+            self.write_ports(channel.into(), Some(channel)).await?;
+            channels[channel.index() as usize] = probe_all(&mut self.i2c, self.address, upstream)
+                .await
+                .map_err(|error| PCA9548Error::i2c(Phase::Probe, Some(channel), error))?;
+        }
+
+        Ok(DeviceMap::from_probes(upstream, channels))
+    }
+
+    /// Applies the conflict policy to a port selection about to be written
+    pub(crate) fn check_conflicts(&mut self, ports: PortSet) -> Result<(), I2C::Error> {
+        let Some(conflict) = self.devices.and_then(|devices| devices.conflict(ports)) else {
+            return Ok(());
+        };
+        match self.conflict_policy {
+            ConflictPolicy::Refuse => Err(PCA9548Error::ConflictError(conflict)),
+            ConflictPolicy::Warn => {
+                self.conflict = Some(conflict);
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the control register, errors name `channel` if the selection
+    /// is about a single one
+    pub(crate) async fn write_ports(
+        &mut self,
+        ports: PortSet,
+        channel: Option<Channel>,
+    ) -> Result<(), I2C::Error> {
+        let code = V::encode(ports).ok_or(PCA9548Error::PortError)?;
+        self.i2c
+            .write(self.address, &[code])
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::Select, channel, error))?;
+        self.state = ports;
+
+        if self.verify {
+            let actual = self.i2c_read(channel).await?;
+            self.state = V::decode(actual);
+            if self.state != ports {
+                return Err(PCA9548Error::VerifyError {
+                    expected: code,
+                    actual,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn i2c_read(&mut self, channel: Option<Channel>) -> Result<u8, I2C::Error> {
+        let mut buffer = [0u8; 1];
         self.i2c
-            .write(self.address, bytes)
+            .read(self.address, &mut buffer)
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::ReadBack, channel, error))
+            .and(Ok(buffer[0]))
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "PCA9548",
+        idents(AsyncI2c(sync = "I2c"), AsyncDelayNs(sync = "DelayNs"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+    RST: OutputPin,
+    DELAY: AsyncDelayNs,
+{
+    /// Pulses the RESET pin, leaving all ports disabled
+    pub async fn reset(&mut self) -> Result<(), I2C::Error> {
+        self.reset_pin
+            .pulse(&mut self.delay)
+            .await
+            .map_err(|_| PCA9548Error::PinError)?;
+        self.state = PortSet::default();
+        Ok(())
+    }
+
+    /// Pulses the RESET pin and restores the previous port selection
+    pub async fn reset_and_restore(&mut self) -> Result<(), I2C::Error> {
+        let previous = self.state;
+        self.reset().await?;
+        self.write_ports(previous, None).await
+    }
+
+    /// Resets the device and restores the previous port selection if `error`
+    /// indicates a bus lock-up, see [`needs_reset`]
+    ///
+    /// Returns `true` if the device was reset.
+    pub async fn recover(&mut self, error: &impl Error) -> Result<bool, I2C::Error> {
+        if !needs_reset(error.kind()) {
+            return Ok(false);
+        }
+        self.reset_and_restore().await?;
+        Ok(true)
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "PCA9548",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+    RST: ResetPin<DELAY>,
+{
+    /// Pulses the RESET pin, if there is one, and restores the port selection
+    ///
+    /// Returns `false` without touching the bus if there is no reset pin.
+    pub(crate) async fn pulse_and_restore(&mut self) -> Result<bool, I2C::Error> {
+        let previous = self.state;
+        let reset = self
+            .reset_pin
+            .pulse(&mut self.delay)
+            .await
+            .map_err(|_| PCA9548Error::PinError)?;
+        if !reset {
+            return Ok(false);
+        }
+        self.state = PortSet::default();
+        self.write_ports(previous, None).await?;
+        Ok(true)
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "PCA9548",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Interrupts,
+{
+    /// Reads the control register and returns the ports whose INT input is asserted
+    pub async fn read_interrupts(&mut self) -> Result<PortSet, I2C::Error> {
+        let code = self.i2c_read(None).await?;
+        self.state = V::decode(code);
+        Ok(V::interrupts(code))
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
+where
+    I2C: I2c,
+    V: Interrupts,
+{
+    /// Checks the combined INT output and, if it is asserted, returns the
+    /// ports whose INT input is asserted
+    ///
+    /// Returns an empty set without touching the bus if INT is not asserted.
+    pub fn poll_interrupts<P>(&mut self, int: &mut P) -> Result<PortSet, I2C::Error>
+    where
+        P: InputPin,
+    {
+        if int.is_high().map_err(|_| PCA9548Error::PinError)? {
+            return Ok(PortSet::default());
+        }
+        self.read_interrupts()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Interrupts,
+{
+    /// Waits for the combined INT output to be asserted and returns the ports
+    /// whose INT input is asserted
+    pub async fn wait_for_interrupt<P>(&mut self, int: &mut P) -> Result<PortSet, I2C::Error>
+    where
+        P: Wait,
+    {
+        int.wait_for_low()
             .await
-            .map_err(PCA9548Error::I2CError)
+            .map_err(|_| PCA9548Error::PinError)?;
+        self.read_interrupts().await
     }
 }
 
 #[cfg(test)]
 mod test {
+    extern crate alloc;
     use crate::prelude::*;
+    use alloc::vec;
     use embedded_hal_mock::common::Generic;
     use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
     use rstest::*;
@@ -92,23 +384,522 @@ mod test {
     }
 
     #[rstest]
-    #[case([true;8], 0b0000_1111)]
+    #[case([true;8], 0b1111_1111)]
     #[case([false;8], 0b0000_0000)]
     #[case([true, false, true, false, false, true, false, true], 0b1010_0101)]
     fn setup_ports(#[case] ports: [bool; 8], #[case] result: u8) {
-        assert_eq!(PCA9548::<Mock>::port_code(ports), result)
+        assert_eq!(PortSet::from(ports).bits(), result)
     }
 
     #[rstest]
-    #[case([true;3], 0b1110_0111)]
-    #[case([false;3], 0b1110_0000)]
-    #[case([true, false, false], 0b1110_0001)]
-    #[case([false, true, false], 0b1110_0010)]
-    #[case([true, false, true], 0b1110_0101)]
+    #[case([true;3], 0x77)]
+    #[case([false;3], 0x70)]
+    #[case([true, false, false], 0x71)]
+    #[case([false, true, false], 0x72)]
+    #[case([true, false, true], 0x75)]
     fn setup_address(#[case] addr: [bool; 3], #[case] result: u8) {
         let i2c = Mock::new(&[]);
-        let multiplexer = PCA9548::new(i2c).with_address_pins(addr[0], addr[1], addr[2]);
+        let multiplexer = PCA9548::new(i2c)
+            .with_address_pins(addr[0], addr[1], addr[2])
+            .unwrap();
         assert_eq!(multiplexer.address, result);
         multiplexer.done();
     }
+
+    #[test]
+    fn channel_range() {
+        for index in 0..8 {
+            assert_eq!(Channel::new(index).map(Channel::index), Some(index));
+        }
+        assert_eq!(Channel::new(8), None);
+        assert_eq!(Channel::new(u8::MAX), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn set_every_port() {
+        let mut expectations = vec![];
+        for channel in Channel::ALL {
+            expectations.push(Transaction::write(0x70, vec![1 << channel.index()]));
+            expectations.push(Transaction::write(0x70, vec![0]));
+        }
+        let i2c = Mock::new(&expectations);
+        let mut multiplexer = PCA9548::new(i2c);
+        for channel in Channel::ALL {
+            multiplexer.set_port(channel, true).unwrap();
+            assert_eq!(multiplexer.ports(), PortSet::from(channel));
+            multiplexer.set_port(channel, false).unwrap();
+        }
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn set_ports_updates_state() {
+        let i2c = Mock::new(&[Transaction::write(0x70, vec![0b0000_0101])]);
+        let mut multiplexer = PCA9548::new(i2c);
+        multiplexer
+            .set_ports([true, false, true, false, false, false, false, false])
+            .unwrap();
+        assert_eq!(multiplexer.ports(), PortSet::from_bits(0b0000_0101));
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn read_ports_syncs_state() {
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::read(0x70, vec![0b0000_0000]),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c);
+        multiplexer.set_port(Channel::CH1, true).unwrap();
+        assert!(multiplexer.sync_state().unwrap());
+        assert!(multiplexer.ports().is_empty());
+        multiplexer.done();
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn scan_expectations(
+        mux: u8,
+        previous: u8,
+        upstream: &[u8],
+        devices: &[(Channel, u8)],
+    ) -> vec::Vec<Transaction> {
+        use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+
+        let probe = |address: u8, found: bool| {
+            let transaction = Transaction::read(address, vec![0]);
+            if found {
+                transaction
+            } else {
+                transaction.with_error(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+            }
+        };
+
+        let mut expectations = vec![Transaction::write(mux, vec![0])];
+        for address in 0x08..=0x77 {
+            if address != mux {
+                expectations.push(probe(address, upstream.contains(&address)));
+            }
+        }
+        for channel in Channel::ALL {
+            expectations.push(Transaction::write(mux, vec![channel.mask()]));
+            for address in 0x08..=0x77 {
+                if address != mux && !upstream.contains(&address) {
+                    expectations.push(probe(address, devices.contains(&(channel, address))));
+                }
+            }
+        }
+        expectations.push(Transaction::write(mux, vec![previous]));
+        expectations
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn scan_all_ports() {
+        let devices = [
+            (Channel::CH0, 0x10),
+            (Channel::CH3, 0x10),
+            (Channel::CH3, 0x29),
+            (Channel::CH7, 0x77),
+        ];
+        let expectations = scan_expectations(0x70, 0, &[0x08, 0x50], &devices);
+        let i2c = Mock::new(&expectations);
+        let mut multiplexer = PCA9548::new(i2c);
+
+        let map = multiplexer.scan().unwrap();
+        assert_eq!(map.upstream().collect::<vec::Vec<_>>(), [0x08, 0x50]);
+        assert!(map.is_upstream(0x50));
+        for (channel, address) in devices {
+            assert!(map.contains(channel, address));
+        }
+        assert_eq!(
+            map.devices(Channel::CH3).collect::<vec::Vec<_>>(),
+            [0x10, 0x29]
+        );
+        assert_eq!(map.devices(Channel::CH1).count(), 0);
+        assert_eq!(map.channels_with(0x10), PortSet::from_bits(0b0000_1001));
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn scan_error_wins_over_restore_error() {
+        use embedded_hal::i2c::ErrorKind;
+
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0]).with_error(ErrorKind::Other),
+            Transaction::write(0x70, vec![0]).with_error(ErrorKind::Bus),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c);
+        assert_eq!(
+            multiplexer.scan(),
+            Err(PCA9548Error::I2CError {
+                mux: None,
+                channel: None,
+                phase: Phase::Select,
+                error: ErrorKind::Other
+            })
+        );
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn reset_and_restore() {
+        use embedded_hal_mock::eh1::delay::{CheckedDelay, Transaction as DelayTransaction};
+        use embedded_hal_mock::eh1::digital::{
+            Mock as PinMock, State, Transaction as PinTransaction,
+        };
+
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0100]),
+            Transaction::write(0x70, vec![0b0000_0100]),
+            Transaction::write(0x70, vec![0b0000_0100]),
+        ]);
+        let pin = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let delay = CheckedDelay::new(&[
+            DelayTransaction::delay_ns(6),
+            DelayTransaction::delay_ns(500),
+            DelayTransaction::delay_ns(6),
+            DelayTransaction::delay_ns(500),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c).with_reset_pin(pin, delay);
+
+        multiplexer.set_port(Channel::CH2, true).unwrap();
+        multiplexer.reset().unwrap();
+        assert!(multiplexer.ports().is_empty());
+
+        assert!(
+            !multiplexer
+                .recover(&embedded_hal::i2c::ErrorKind::Other)
+                .unwrap()
+        );
+        multiplexer.set_port(Channel::CH2, true).unwrap();
+        assert!(
+            multiplexer
+                .recover(&embedded_hal::i2c::ErrorKind::ArbitrationLoss)
+                .unwrap()
+        );
+        assert_eq!(multiplexer.ports(), PortSet::from(Channel::CH2));
+
+        let (mut i2c, mut pin, mut delay) = multiplexer.release();
+        i2c.done();
+        pin.done();
+        delay.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn family_variants() {
+        use crate::variant::{Pca9545, Pca9546};
+
+        let mut i2c = Mock::new(&[
+            Transaction::write(0x73, vec![0b0000_1111]),
+            Transaction::write(0x70, vec![0b0000_1011]),
+            Transaction::write(0x70, vec![0b0000_0000]),
+            Transaction::read(0x71, vec![0b1000_0010]),
+        ]);
+
+        let mut switch = PCA9548::new(&mut i2c)
+            .with_variant::<Pca9546>()
+            .unwrap()
+            .with_address_pins(true, true, false)
+            .unwrap();
+        assert_eq!(
+            switch.set_port(Channel::CH4, true),
+            Err(PCA9548Error::PortError)
+        );
+        switch.set_ports(Pca9546::all_ports()).unwrap();
+
+        let mut mux: PCA9547<_> = PCA9548::new(&mut i2c).with_variant().unwrap();
+        mux.set_port(Channel::CH3, true).unwrap();
+        assert_eq!(
+            mux.set_port(Channel::CH4, true),
+            Err(PCA9548Error::PortError)
+        );
+        assert_eq!(mux.ports(), PortSet::from(Channel::CH3));
+        assert_eq!(mux.set_ports_enabled(), Err(PCA9548Error::PortError));
+
+        let mut mux: PCA9547<_> = PCA9548::new(&mut i2c).with_variant().unwrap();
+        mux.set_port(Channel::CH3, false).unwrap();
+
+        let mut switch = PCA9548::new(&mut i2c)
+            .with_variant::<Pca9545>()
+            .unwrap()
+            .with_address_pins(true, false, false)
+            .unwrap();
+        assert_eq!(switch.read_ports().unwrap(), PortSet::from(Channel::CH1));
+
+        i2c.done();
+    }
+
+    #[test]
+    fn rejects_addresses_the_variant_lacks() {
+        use crate::variant::{Pca9543, Pca9545};
+
+        let mut i2c = Mock::new(&[]);
+        assert!(PCA9548::new(&mut i2c).with_address(0x77).is_some());
+        assert!(PCA9548::new(&mut i2c).with_address(0x78).is_none());
+        let switch = PCA9548::new(&mut i2c).with_variant::<Pca9545>().unwrap();
+        assert!(switch.with_address(0x77).is_none());
+        let switch = PCA9548::new(&mut i2c).with_variant::<Pca9543>().unwrap();
+        assert!(switch.with_address_pins(false, false, true).is_none());
+        // A2 set for an 8-channel part does not carry over to a 2-pin part
+        let mux = PCA9548::new(&mut i2c)
+            .with_address_pins(false, false, true)
+            .unwrap();
+        assert!(mux.with_variant::<Pca9545>().is_none());
+        i2c.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn poll_interrupts() {
+        use embedded_hal_mock::eh1::digital::{
+            Mock as PinMock, State, Transaction as PinTransaction,
+        };
+
+        let mut i2c = Mock::new(&[Transaction::read(0x70, vec![0b0100_0001])]);
+        let mut int = PinMock::new(&[
+            PinTransaction::get(State::High),
+            PinTransaction::get(State::Low),
+        ]);
+        let mut switch: PCA9545<_> = PCA9548::new(&mut i2c).with_variant().unwrap();
+
+        assert!(switch.poll_interrupts(&mut int).unwrap().is_empty());
+        assert_eq!(
+            switch.poll_interrupts(&mut int).unwrap(),
+            PortSet::from(Channel::CH2)
+        );
+        assert_eq!(switch.ports(), PortSet::from(Channel::CH0));
+
+        i2c.done();
+        int.done();
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn wait_for_interrupt() {
+        use embedded_hal_mock::eh1::digital::{
+            Mock as PinMock, State, Transaction as PinTransaction,
+        };
+
+        let mut i2c = Mock::new(&[Transaction::read(0x70, vec![0b0010_0000])]);
+        let mut int = PinMock::new(&[PinTransaction::wait_for_state(State::Low)]);
+        let mut switch: PCA9543<_> = PCA9548::new(&mut i2c).with_variant().unwrap();
+
+        let flags = embassy_futures::block_on(switch.wait_for_interrupt(&mut int)).unwrap();
+        assert_eq!(flags, PortSet::from(Channel::CH1));
+
+        i2c.done();
+        int.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn verify_after_write() {
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0001_0000]),
+            Transaction::read(0x70, vec![0b0001_0000]),
+            Transaction::write(0x70, vec![0b0011_0000]),
+            Transaction::read(0x70, vec![0b0000_0000]),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c).with_verify(true);
+        assert!(multiplexer.set_port(Channel::CH4, true).is_ok());
+        assert_eq!(
+            multiplexer.set_port(Channel::CH5, true),
+            Err(PCA9548Error::VerifyError {
+                expected: 0b0011_0000,
+                actual: 0b0000_0000
+            })
+        );
+        assert!(multiplexer.ports().is_empty());
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn errors_name_channel_and_phase() {
+        use embedded_hal::i2c::ErrorKind;
+
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_1000]).with_error(ErrorKind::Other),
+            Transaction::write(0x70, vec![0b0000_1000]),
+            Transaction::read(0x70, vec![0]).with_error(ErrorKind::Other),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c).with_verify(true);
+        assert_eq!(
+            multiplexer.set_port(Channel::CH3, true),
+            Err(PCA9548Error::I2CError {
+                mux: None,
+                channel: Some(Channel::CH3),
+                phase: Phase::Select,
+                error: ErrorKind::Other
+            })
+        );
+        let error = multiplexer.set_port(Channel::CH3, true).unwrap_err();
+        assert_eq!(error.phase(), Some(Phase::ReadBack));
+        assert_eq!(error.channel(), Some(Channel::CH3));
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn broadcast_write() {
+        use embedded_hal::i2c::ErrorKind;
+
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::write(0x70, vec![0b1010_0000]),
+            Transaction::write(0x29, vec![0x06, 0x01]),
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::write(0x70, vec![0b0000_0011]),
+            Transaction::write(0x29, vec![0x07]).with_error(ErrorKind::Other),
+            Transaction::write(0x70, vec![0b0000_0001]),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c).with_port(Channel::CH0, true).unwrap();
+        let channels = PortSet::from_bits(0b1010_0000);
+        assert!(
+            multiplexer
+                .broadcast_write(channels, 0x29, &[0x06, 0x01])
+                .is_ok()
+        );
+        assert_eq!(multiplexer.ports(), PortSet::from(Channel::CH0));
+        // The selection is restored when the broadcast fails
+        assert_eq!(
+            multiplexer.broadcast_write(PortSet::from_bits(0b11), 0x29, &[0x07]),
+            Err(PCA9548Error::I2CError {
+                mux: None,
+                channel: None,
+                phase: Phase::Device,
+                error: ErrorKind::Other
+            })
+        );
+        assert_eq!(multiplexer.ports(), PortSet::from(Channel::CH0));
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn broadcast_restores_after_failed_select() {
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::read(0x70, vec![0b0000_0001]),
+            Transaction::write(0x70, vec![0b0000_0110]),
+            Transaction::read(0x70, vec![0b0000_0100]),
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::read(0x70, vec![0b0000_0001]),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c)
+            .with_verify(true)
+            .with_port(Channel::CH0, true)
+            .unwrap();
+        assert_eq!(
+            multiplexer.broadcast_write(PortSet::from_bits(0b0000_0110), 0x29, &[0x07]),
+            Err(PCA9548Error::VerifyError {
+                expected: 0b0000_0110,
+                actual: 0b0000_0100
+            })
+        );
+        assert_eq!(multiplexer.ports(), PortSet::from(Channel::CH0));
+        multiplexer.done();
+    }
+
+    fn sensors() -> DeviceMap {
+        let mut devices = DeviceMap::new();
+        devices.insert(Channel::CH0, 0x04);
+        devices.insert(Channel::CH1, 0x04);
+        devices.insert(Channel::CH2, 0x29);
+        devices
+    }
+
+    #[test]
+    fn address_conflicts() {
+        let mut devices = sensors();
+        assert_eq!(devices.conflict(PortSet::from_bits(0b0000_0101)), None);
+        assert_eq!(
+            devices.conflict(PortSet::from_bits(0b0000_0111)),
+            Some(AddressConflict {
+                address: 0x04,
+                channels: PortSet::from_bits(0b0000_0011),
+                upstream: false,
+            })
+        );
+
+        devices.insert_upstream(0x29);
+        assert_eq!(
+            devices.conflict(Channel::CH2.into()),
+            Some(AddressConflict {
+                address: 0x29,
+                channels: Channel::CH2.into(),
+                upstream: true,
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn refuse_conflicting_ports() {
+        let i2c = Mock::new(&[Transaction::write(0x70, vec![0b0000_0001])]);
+        let mut multiplexer = PCA9548::new(i2c).with_devices(sensors());
+        multiplexer.set_port(Channel::CH0, true).unwrap();
+        assert_eq!(
+            multiplexer.set_port(Channel::CH1, true),
+            Err(PCA9548Error::ConflictError(AddressConflict {
+                address: 0x04,
+                channels: PortSet::from_bits(0b0000_0011),
+                upstream: false,
+            }))
+        );
+        assert!(matches!(
+            multiplexer.set_ports([true; 8]),
+            Err(PCA9548Error::ConflictError(_))
+        ));
+        assert_eq!(multiplexer.ports(), PortSet::from(Channel::CH0));
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn warn_about_conflicting_ports() {
+        let i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0101]),
+            Transaction::write(0x70, vec![0b0000_0011]),
+        ]);
+        let mut multiplexer = PCA9548::new(i2c)
+            .with_devices(sensors())
+            .with_conflict_policy(ConflictPolicy::Warn);
+        multiplexer
+            .set_ports(PortSet::from_bits(0b0000_0101))
+            .unwrap();
+        assert_eq!(multiplexer.take_conflict(), None);
+        multiplexer
+            .set_ports(PortSet::from_bits(0b0000_0011))
+            .unwrap();
+        assert_eq!(
+            multiplexer.take_conflict().map(|conflict| conflict.address),
+            Some(0x04)
+        );
+        assert_eq!(multiplexer.take_conflict(), None);
+        multiplexer.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn broadcast_needs_switch() {
+        let mut i2c = Mock::new(&[]);
+        let mut multiplexer: PCA9547<_> = PCA9548::new(&mut i2c).with_variant().unwrap();
+        assert_eq!(
+            multiplexer.broadcast_write(PortSet::from_bits(0b11), 0x29, &[0x07]),
+            Err(PCA9548Error::PortError)
+        );
+        i2c.done();
+    }
 }
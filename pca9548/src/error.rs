@@ -1,33 +1,141 @@
+use crate::{Channel, MuxId, scan::AddressConflict};
+use core::fmt;
 use embedded_hal::i2c::{Error, ErrorKind};
 use thiserror::Error;
 
 pub type Result<T, I2cError> = core::result::Result<T, PCA9548Error<I2cError>>;
 
-#[derive(Error, Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Error, Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PCA9548Error<I2cError>
 where
     I2cError: Error,
 {
-    #[error("Write Read I2C Error")]
-    WriteReadI2CError,
-    #[error("Write I2C Error")]
-    WriteI2CError,
-    #[error("Read I2C Error")]
-    ReadI2CError,
     #[error("Incorrect port supplied")]
     PortError,
-    #[error("I2C Error")]
-    I2CError(I2cError),
+    #[error("Reset pin error")]
+    PinError,
+    #[error("Control register mismatch (expected {expected:#04x}, read {actual:#04x})")]
+    VerifyError { expected: u8, actual: u8 },
+    #[error("Address {:#04x} is used on several enabled channels", .0.address)]
+    ConflictError(AddressConflict),
+    #[error("Read refused while several channels are enabled")]
+    BroadcastReadError,
+    /// The bus reported `error` while talking to `mux` or to a device behind
+    /// `channel`
+    ///
+    /// `mux` is only known within a `MuxTree`,
+    /// `channel` is the channel being selected on that multiplexer, or the
+    /// channel of the device, `None` if there is no single one.
+    #[error("I2C error during {phase}{}", AtChannel(.channel))]
+    I2CError {
+        mux: Option<MuxId>,
+        channel: Option<Channel>,
+        phase: Phase,
+        error: I2cError,
+    },
 }
 
-impl<I2cError> Error for PCA9548Error<I2cError>
+/// Step of a multiplexed transaction the bus failed in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Writing the control register to select channels
+    Select,
+    /// Reading the control register, e.g. to verify a select
+    ReadBack,
+    /// Disabling a multiplexer sharing a segment of the path, so that the
+    /// devices behind it leave the bus
+    DisableSibling,
+    /// Talking to a device behind the selected channel
+    Device,
+    /// Disabling all channels after use or while idle
+    Deselect,
+    /// Probing addresses during a scan
+    Probe,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Select => "select",
+            Phase::ReadBack => "control register read",
+            Phase::DisableSibling => "sibling disable",
+            Phase::Device => "device transaction",
+            Phase::Deselect => "deselect",
+            Phase::Probe => "probe",
+        })
+    }
+}
+
+/// Formats where an error happened, if the channel is known
+struct AtChannel<'a>(&'a Option<Channel>);
+
+impl fmt::Display for AtChannel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(channel) => write!(f, " on channel {}", channel.index()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<I2cError> PCA9548Error<I2cError>
 where
     I2cError: Error,
 {
-    fn kind(&self) -> ErrorKind {
+    /// I²C error of a multiplexer outside a tree
+    pub(crate) fn i2c(phase: Phase, channel: Option<Channel>, error: I2cError) -> Self {
+        Self::I2CError {
+            mux: None,
+            channel,
+            phase,
+            error,
+        }
+    }
+
+    /// Multiplexer of a tree the failed operation was talking to, if known
+    pub fn mux(&self) -> Option<MuxId> {
+        match self {
+            Self::I2CError { mux, .. } => *mux,
+            _ => None,
+        }
+    }
+
+    /// Channel the failed operation was talking to, if known
+    pub fn channel(&self) -> Option<Channel> {
         match self {
-            Self::I2CError(e) => e.kind(),
-            _ => ErrorKind::Other,
+            Self::I2CError { channel, .. } => *channel,
+            _ => None,
         }
     }
+
+    /// Step the bus failed in, if it reported an error
+    pub fn phase(&self) -> Option<Phase> {
+        match self {
+            Self::I2CError { phase, .. } => Some(*phase),
+            _ => None,
+        }
+    }
+
+    /// Underlying I²C error, if the bus reported one
+    pub fn i2c_error(&self) -> Option<&I2cError> {
+        match self {
+            Self::I2CError { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<I2cError> Error for PCA9548Error<I2cError>
+where
+    I2cError: Error,
+{
+    fn kind(&self) -> ErrorKind {
+        self.i2c_error().map_or(ErrorKind::Other, Error::kind)
+    }
+}
+
+/// Returns `true` for bus errors that indicate a lock-up the multiplexer
+/// should be reset to recover from.
+pub fn needs_reset(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::Bus | ErrorKind::ArbitrationLoss)
 }
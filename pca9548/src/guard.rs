@@ -0,0 +1,529 @@
+//! Scoped channel selection.
+//!
+//! [`PCA9548::select`] enables a channel and returns a [`ChannelGuard`] that
+//! borrows the driver and talks to devices on the bus. The previous port
+//! selection is restored when the guard is released, or when it is dropped in
+//! blocking mode. Async code has to call [`ChannelGuard::release`] as the
+//! selection cannot be restored from `Drop`.
+//!
+//! While the guard has more than one channel enabled, devices sharing an
+//! address on those channels answer at the same time and the data read back
+//! is the wired-AND of their replies. Reads are refused with
+//! [`PCA9548Error::BroadcastReadError`] in that case, writes still go out to
+//! every enabled channel.
+//!
+//! With [`Recovery::ResetAndRetry`], a transaction failing with a bus lock-up
+//! resets the multiplexer, restores the guard's selection and is retried once.
+
+use crate::{
+    Channel, PCA9548, PortSet,
+    error::{PCA9548Error, Phase, Result, needs_reset},
+    reset::{Recovery, ResetPin},
+    variant::Variant,
+};
+#[cfg(not(feature = "async"))]
+use embedded_hal::i2c::I2c;
+use embedded_hal::i2c::{Error, ErrorType, Operation, SevenBitAddress};
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+/// Channel selection that is undone when the guard is released
+#[cfg_attr(
+    feature = "async",
+    must_use = "call `release` to restore the previous port selection"
+)]
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "ChannelGuard",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+pub struct ChannelGuard<'a, I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+{
+    mux: &'a mut PCA9548<I2C, V, RST, DELAY>,
+    channel: Channel,
+    previous: PortSet,
+    restored: bool,
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "PCA9548",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+{
+    /// Enables `channel` in addition to the ports already enabled until the
+    /// returned guard is released
+    ///
+    /// Multiplexers with an encoded channel field (PCA9544, PCA9547) can only
+    /// enable a single port, so the channel is selected exclusively.
+    pub async fn select(
+        &mut self,
+        channel: Channel,
+    ) -> Result<ChannelGuard<'_, I2C, V, RST, DELAY>, I2C::Error> {
+        let mut ports = self.state;
+        ports.set(channel, true);
+        if V::encode(ports).is_none() {
+            ports = channel.into();
+        }
+        self.guard(channel, ports).await
+    }
+
+    /// Enables `channel` and disables every other port until the returned
+    /// guard is released
+    pub async fn select_exclusive(
+        &mut self,
+        channel: Channel,
+    ) -> Result<ChannelGuard<'_, I2C, V, RST, DELAY>, I2C::Error> {
+        self.guard(channel, channel.into()).await
+    }
+
+    async fn guard(
+        &mut self,
+        channel: Channel,
+        ports: PortSet,
+    ) -> Result<ChannelGuard<'_, I2C, V, RST, DELAY>, I2C::Error> {
+        let previous = self.state;
+        if ports != previous {
+            self.check_conflicts(ports)?;
+            self.write_ports(ports, Some(channel)).await?;
+        }
+        Ok(ChannelGuard {
+            mux: self,
+            channel,
+            previous,
+            restored: false,
+        })
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "ChannelGuard",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> ChannelGuard<'_, I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+{
+    /// Ports enabled while the guard is alive
+    pub fn ports(&self) -> PortSet {
+        self.mux.state
+    }
+
+    /// Restores the previous port selection
+    pub async fn release(mut self) -> Result<(), I2C::Error> {
+        self.restored = true;
+        self.restore().await
+    }
+
+    /// Channel the guard was created for
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    fn device_error(&self, error: I2C::Error) -> PCA9548Error<I2C::Error> {
+        PCA9548Error::i2c(Phase::Device, Some(self.channel), error)
+    }
+
+    fn check_read(&self) -> Result<(), I2C::Error> {
+        if self.mux.state.len() > 1 {
+            return Err(PCA9548Error::BroadcastReadError);
+        }
+        Ok(())
+    }
+
+    async fn restore(&mut self) -> Result<(), I2C::Error> {
+        if self.mux.state == self.previous {
+            return Ok(());
+        }
+        self.mux.write_ports(self.previous, None).await
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "ChannelGuard",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> ChannelGuard<'_, I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+    RST: ResetPin<DELAY>,
+{
+    /// Resets the multiplexer after a bus lock-up if the recovery policy asks
+    /// for it, returns `true` if the transaction should be retried
+    async fn recover(
+        &mut self,
+        result: &core::result::Result<(), I2C::Error>,
+    ) -> Result<bool, I2C::Error> {
+        match result {
+            Err(error)
+                if self.mux.recovery == Recovery::ResetAndRetry && needs_reset(error.kind()) =>
+            {
+                self.mux.pulse_and_restore().await
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<I2C, V, RST, DELAY> Drop for ChannelGuard<'_, I2C, V, RST, DELAY>
+where
+    I2C: I2c,
+    V: Variant,
+{
+    fn drop(&mut self) {
+        if !self.restored {
+            // Errors cannot be reported from drop, call `release` to see them
+            let _ = self.restore();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C, V, RST, DELAY> Drop for ChannelGuard<'_, I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+{
+    fn drop(&mut self) {
+        // A guard dropped while unwinding is not the mistake being caught
+        #[cfg(feature = "std")]
+        let unwinding = std::thread::panicking();
+        #[cfg(not(feature = "std"))]
+        let unwinding = false;
+        debug_assert!(
+            self.restored || self.mux.state == self.previous || unwinding,
+            "channel guard dropped without `release`, {:?} is still selected",
+            self.channel
+        );
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "ChannelGuard",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> ErrorType for ChannelGuard<'_, I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+{
+    type Error = PCA9548Error<I2C::Error>;
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "ChannelGuard",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, V, RST, DELAY> AsyncI2c for ChannelGuard<'_, I2C, V, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    V: Variant,
+    RST: ResetPin<DELAY>,
+{
+    async fn read(
+        &mut self,
+        address: SevenBitAddress,
+        read: &mut [u8],
+    ) -> core::result::Result<(), Self::Error> {
+        self.check_read()?;
+        let mut result = self.mux.i2c.read(address, read).await;
+        if self.recover(&result).await? {
+            result = self.mux.i2c.read(address, read).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+
+    async fn write(
+        &mut self,
+        address: SevenBitAddress,
+        write: &[u8],
+    ) -> core::result::Result<(), Self::Error> {
+        let mut result = self.mux.i2c.write(address, write).await;
+        if self.recover(&result).await? {
+            result = self.mux.i2c.write(address, write).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+
+    async fn write_read(
+        &mut self,
+        address: SevenBitAddress,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> core::result::Result<(), Self::Error> {
+        self.check_read()?;
+        let mut result = self.mux.i2c.write_read(address, write, read).await;
+        if self.recover(&result).await? {
+            result = self.mux.i2c.write_read(address, write, read).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> core::result::Result<(), Self::Error> {
+        if operations
+            .iter()
+            .any(|operation| matches!(operation, Operation::Read(_)))
+        {
+            self.check_read()?;
+        }
+        let mut result = self.mux.i2c.transaction(address, operations).await;
+        if self.recover(&result).await? {
+            result = self.mux.i2c.transaction(address, operations).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate alloc;
+    use crate::prelude::*;
+    use alloc::vec;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn restores_on_drop() {
+        use embedded_hal::i2c::I2c;
+
+        let mut i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::write(0x70, vec![0b0000_1001]),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::write(0x70, vec![0b0001_0000]),
+            Transaction::write_read(0x04, vec![0x02], vec![0x03]),
+            Transaction::write(0x70, vec![0b0000_0001]),
+        ]);
+        let mut mux = PCA9548::new(&mut i2c);
+        mux.set_port(Channel::CH0, true).unwrap();
+
+        let send = |mux: &mut PCA9548<_>| -> Result<(), PCA9548Error<_>> {
+            let mut guard = mux.select(Channel::CH3)?;
+            assert_eq!(guard.ports(), PortSet::from_bits(0b0000_1001));
+            guard.write(0x04, &[0x01])?;
+            // Early return, the guard still restores the selection
+            Err(PCA9548Error::PortError)
+        };
+        assert!(send(&mut mux).is_err());
+        assert_eq!(mux.ports(), PortSet::from(Channel::CH0));
+
+        let mut guard = mux.select_exclusive(Channel::CH4).unwrap();
+        let mut data = [0];
+        guard.write_read(0x04, &[0x02], &mut data).unwrap();
+        assert_eq!(data, [0x03]);
+        guard.release().unwrap();
+        assert_eq!(mux.ports(), PortSet::from(Channel::CH0));
+
+        i2c.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn multiplexer_selects_exclusively() {
+        let mut i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0100]),
+            Transaction::write(0x70, vec![0b0000_0101]),
+            Transaction::write(0x70, vec![0b0000_0100]),
+        ]);
+        let mut mux: PCA9544<_> = PCA9548::new(&mut i2c).with_variant().unwrap();
+        mux.set_port(Channel::CH0, true).unwrap();
+
+        let guard = mux.select(Channel::CH1).unwrap();
+        assert_eq!(guard.ports(), PortSet::from(Channel::CH1));
+        drop(guard);
+
+        i2c.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn refuses_broadcast_reads() {
+        use embedded_hal::i2c::{I2c, Operation};
+
+        let mut i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::write(0x70, vec![0b0000_0011]),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::write(0x70, vec![0b0000_0001]),
+        ]);
+        let mut mux = PCA9548::new(&mut i2c);
+        mux.set_port(Channel::CH0, true).unwrap();
+
+        let mut guard = mux.select(Channel::CH1).unwrap();
+        let mut data = [0];
+        assert_eq!(
+            guard.read(0x04, &mut data),
+            Err(PCA9548Error::BroadcastReadError)
+        );
+        assert_eq!(
+            guard.write_read(0x04, &[0x02], &mut data),
+            Err(PCA9548Error::BroadcastReadError)
+        );
+        assert_eq!(
+            guard.transaction(0x04, &mut [Operation::Read(&mut data)]),
+            Err(PCA9548Error::BroadcastReadError)
+        );
+        guard.write(0x04, &[0x01]).unwrap();
+        guard.release().unwrap();
+
+        i2c.done();
+    }
+
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn resets_and_retries_after_lock_up() {
+        use embedded_hal::i2c::{ErrorKind, I2c};
+        use embedded_hal_mock::eh1::delay::{CheckedDelay, Transaction as DelayTransaction};
+        use embedded_hal_mock::eh1::digital::{
+            Mock as PinMock, State, Transaction as PinTransaction,
+        };
+
+        let mut i2c = Mock::new(&[
+            // Lock-ups are reported by default
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]).with_error(ErrorKind::Bus),
+            Transaction::write(0x70, vec![0b0000_0000]),
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]).with_error(ErrorKind::Bus),
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]),
+            // Only once per transaction
+            Transaction::read(0x04, vec![0x00]).with_error(ErrorKind::Bus),
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::read(0x04, vec![0x00]).with_error(ErrorKind::Bus),
+            // Other errors are not retried
+            Transaction::read(0x04, vec![0x00]).with_error(ErrorKind::Other),
+            Transaction::write(0x70, vec![0b0000_0000]),
+        ]);
+        let pin = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let delay = CheckedDelay::new(&[
+            DelayTransaction::delay_ns(6),
+            DelayTransaction::delay_ns(500),
+            DelayTransaction::delay_ns(6),
+            DelayTransaction::delay_ns(500),
+        ]);
+
+        let mut mux = PCA9548::new(&mut i2c);
+        let mut guard = mux.select(Channel::CH1).unwrap();
+        assert_eq!(
+            guard.write(0x04, &[0x01]),
+            Err(PCA9548Error::I2CError {
+                mux: None,
+                channel: Some(Channel::CH1),
+                phase: Phase::Device,
+                error: ErrorKind::Bus
+            })
+        );
+        guard.release().unwrap();
+
+        let mut mux = PCA9548::new(&mut i2c)
+            .with_reset_pin(pin, delay)
+            .with_recovery(Recovery::ResetAndRetry);
+        let mut guard = mux.select(Channel::CH1).unwrap();
+        guard.write(0x04, &[0x01]).unwrap();
+        let mut data = [0];
+        assert_eq!(
+            guard.read(0x04, &mut data),
+            Err(PCA9548Error::I2CError {
+                mux: None,
+                channel: Some(Channel::CH1),
+                phase: Phase::Device,
+                error: ErrorKind::Bus
+            })
+        );
+        assert_eq!(
+            guard.read(0x04, &mut data),
+            Err(PCA9548Error::I2CError {
+                mux: None,
+                channel: Some(Channel::CH1),
+                phase: Phase::Device,
+                error: ErrorKind::Other
+            })
+        );
+        guard.release().unwrap();
+
+        let (_, mut pin, mut delay) = mux.release();
+        pin.done();
+        delay.done();
+        i2c.done();
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn release() {
+        use embassy_futures::block_on;
+        use embedded_hal_async::i2c::I2c;
+
+        let mut i2c = Mock::new(&[
+            Transaction::write(0x70, vec![0b0100_0000]),
+            Transaction::read(0x04, vec![0x01]),
+            Transaction::write(0x70, vec![0b0000_0000]),
+        ]);
+        let mut mux = PCA9548::new(&mut i2c);
+
+        block_on(async {
+            let mut guard = mux.select(Channel::CH6).await.unwrap();
+            let mut data = [0];
+            guard.read(0x04, &mut data).await.unwrap();
+            assert_eq!(data, [0x01]);
+            guard.release().await.unwrap();
+        });
+        assert!(mux.ports().is_empty());
+
+        i2c.done();
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    #[should_panic(expected = "dropped without `release`")]
+    fn dropping_unreleased_guard_panics_in_debug() {
+        use embassy_futures::block_on;
+
+        let mut i2c = Mock::new(&[Transaction::write(0x70, vec![0b0100_0000])]);
+        let mut mux = PCA9548::new(&mut i2c);
+        let guard = block_on(mux.select(Channel::CH6)).unwrap();
+        drop(guard);
+    }
+}
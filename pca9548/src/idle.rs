@@ -0,0 +1,153 @@
+//! What happens to the selected channel between transactions of a
+//! [`BusPort`](crate::bus::BusPort).
+//!
+//! Keeping a channel selected saves a control register write on the next
+//! transaction, but leaves the devices on that channel connected to the bus
+//! with their capacitance and the risk of holding SDA low.
+
+use crate::cache::IdleCache;
+
+/// Handling of the selected channel once a transaction is over
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum IdlePolicy {
+    /// Leave the channel selected until another port selects its own, or
+    /// until the bus goes idle, see
+    /// [`MultiplexerBus::with_deselect_after_idle`](crate::bus::MultiplexerBus::with_deselect_after_idle)
+    #[default]
+    KeepSelected,
+    /// Disable all channels after every transaction
+    DeselectAfterUse,
+}
+
+/// Millisecond time source deciding when the bus went idle
+pub trait IdleClock {
+    /// Milliseconds since an arbitrary starting point, allowed to wrap
+    fn now_ms(&self) -> u32;
+}
+
+impl<T> IdleClock for &T
+where
+    T: IdleClock,
+{
+    fn now_ms(&self) -> u32 {
+        T::now_ms(self)
+    }
+}
+
+/// Decides when the bus went idle, keeping the time of the last transaction
+/// in the cache `C` shared by the ports of a bus
+pub trait IdleTimer<C> {
+    /// Records a transaction that left a channel selected
+    fn used(&self, cache: &C);
+
+    /// Records that all channels were disabled
+    fn deselected(&self, cache: &C);
+
+    /// Returns `true` if a channel was left selected and the bus has been
+    /// idle since for at least the timeout
+    fn expired(&self, cache: &C) -> bool;
+}
+
+/// The bus never goes idle, channels stay selected until the idle policy
+/// disables them
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoTimeout;
+
+impl<C> IdleTimer<C> for NoTimeout {
+    fn used(&self, _cache: &C) {}
+
+    fn deselected(&self, _cache: &C) {}
+
+    fn expired(&self, _cache: &C) -> bool {
+        false
+    }
+}
+
+/// The bus goes idle once no port was used for a number of milliseconds
+/// measured with the clock `K`
+#[derive(Copy, Clone, Debug)]
+pub struct IdleTimeout<K> {
+    timeout: u32,
+    clock: K,
+}
+
+impl<K> IdleTimeout<K>
+where
+    K: IdleClock,
+{
+    pub const fn new(timeout: u32, clock: K) -> Self {
+        Self { timeout, clock }
+    }
+}
+
+impl<C, K> IdleTimer<C> for IdleTimeout<K>
+where
+    C: IdleCache,
+    K: IdleClock,
+{
+    fn used(&self, cache: &C) {
+        cache.set_last_used(Some(self.clock.now_ms()));
+    }
+
+    fn deselected(&self, cache: &C) {
+        cache.set_last_used(None);
+    }
+
+    fn expired(&self, cache: &C) -> bool {
+        cache
+            .last_used()
+            .is_some_and(|last_used| self.clock.now_ms().wrapping_sub(last_used) >= self.timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::RefCellCache;
+    use core::cell::Cell;
+
+    struct TestClock(Cell<u32>);
+
+    impl IdleClock for TestClock {
+        fn now_ms(&self) -> u32 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn expiry() {
+        let clock = TestClock(Cell::new(100));
+        let timer = IdleTimeout::new(10, &clock);
+        let cache = RefCellCache::new();
+        assert!(!timer.expired(&cache));
+
+        timer.used(&cache);
+        clock.0.set(109);
+        assert!(!timer.expired(&cache));
+        clock.0.set(110);
+        assert!(timer.expired(&cache));
+
+        timer.deselected(&cache);
+        clock.0.set(200);
+        assert!(!timer.expired(&cache));
+    }
+
+    #[test]
+    fn clock_wraps() {
+        let clock = TestClock(Cell::new(u32::MAX - 4));
+        let timer = IdleTimeout::new(10, &clock);
+        let cache = RefCellCache::new();
+        timer.used(&cache);
+        clock.0.set(4);
+        assert!(!timer.expired(&cache));
+        clock.0.set(5);
+        assert!(timer.expired(&cache));
+    }
+
+    #[test]
+    fn no_timeout() {
+        let cache = RefCellCache::new();
+        NoTimeout.used(&cache);
+        assert!(!NoTimeout.expired(&cache));
+    }
+}
@@ -1,6 +1,15 @@
 //! This is a platform agnostic Rust driver for the TCA9548A 8-Channel I2C
 //! Multiplexer, based on the [`embedded-hal`] traits.
 //!
+//! The rest of the PCA954x family (PCA9543, PCA9544, PCA9545, PCA9546 and
+//! PCA9547) is supported through the [`variant`] module.
+//!
+//! The `embassy` feature adds the `shared` module, which lets several async
+//! tasks talk to devices on different channels through one bus.
+//!
+//! On Linux boards where the kernel drives the multiplexer, the `linux`
+//! feature enables the `linux` module to find the adapter of each channel.
+//!
 //! [`embedded-hal`]: https://github.com/rust-embedded/embedded-hal
 //!
 //! Datasheet:
@@ -18,25 +27,70 @@
 //! let dev = I2cdev::new("/dev/i2c-1").unwrap();
 //! let mut mux = PCA9548::new(dev).with_ports_disabled().unwrap();
 //! // Enable port 0
-//! mux.set_port(0, true).unwrap();
+//! mux.set_port(Channel::CH0, true).unwrap();
 //! ```
 
 #![deny(unsafe_code)]
 #![no_std]
 
+use crate::reset::{NoPin, Recovery};
+use crate::scan::{AddressConflict, ConflictPolicy, DeviceMap};
+use crate::variant::Variant;
+use core::marker::PhantomData;
+
+#[cfg(feature = "std")]
+extern crate std;
+
 #[cfg(feature = "bus")]
 pub mod bus;
+#[cfg(feature = "bus")]
+pub mod cache;
 pub mod device;
 pub mod error;
+pub mod guard;
+#[cfg(feature = "bus")]
+pub mod idle;
+#[cfg(feature = "linux")]
+pub mod linux;
+pub mod reset;
+pub mod scan;
+#[cfg(feature = "embassy")]
+pub mod shared;
+#[cfg(feature = "bus")]
+pub mod tree;
+pub mod variant;
 
 pub mod prelude {
     #[cfg(feature = "bus")]
     pub use crate::bus::{BusPort, MultiplexerBus};
-    pub use crate::{PCA9548, PortState, error::PCA9548Error};
+    #[cfg(all(feature = "bus", feature = "critical-section"))]
+    pub use crate::cache::CriticalSectionCache;
+    #[cfg(all(feature = "bus", feature = "std"))]
+    pub use crate::cache::MutexCache;
+    #[cfg(feature = "bus")]
+    pub use crate::cache::{IdleCache, NoCache, RefCellCache, SelectionCache};
+    pub use crate::guard::ChannelGuard;
+    #[cfg(feature = "bus")]
+    pub use crate::idle::{IdleClock, IdlePolicy, IdleTimeout, NoTimeout};
+    pub use crate::reset::{NoPin, Recovery, ResetPin};
+    #[cfg(feature = "embassy")]
+    pub use crate::shared::{SharedMultiplexer, SharedPort};
+    #[cfg(feature = "bus")]
+    pub use crate::tree::{MuxId, MuxTree, TreePort};
+    pub use crate::variant::{Encoding, Interrupts, Variant};
+    pub use crate::{
+        Channel, PCA9543, PCA9544, PCA9545, PCA9546, PCA9547, PCA9548, PortSet, PortState,
+        error::{PCA9548Error, Phase},
+        scan::{AddressConflict, ConflictPolicy, DeviceMap},
+    };
 }
 
 const DEFAULT_DEVICE_ADDRESS: u8 = 0x70;
 const MAX_PORTS: usize = 8;
+/// Minimum RESET low pulse width (TCA9548A t<sub>W(L)</sub>)
+const RESET_PULSE_NS: u32 = 6;
+/// Time for the device to release SDA after RESET (PCA9548A t<sub>rst</sub>)
+const RESET_RECOVERY_NS: u32 = 500;
 
 #[derive(Copy, Clone, Debug)]
 pub enum PortState {
@@ -53,15 +107,161 @@ impl From<bool> for PortState {
     }
 }
 
+/// Downstream channel of the multiplexer.
+///
+/// Channels are checked when they are created, so a `Channel` always refers
+/// to one of the eight ports of the device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Channel(u8);
+
+impl Channel {
+    pub const CH0: Channel = Channel(0);
+    pub const CH1: Channel = Channel(1);
+    pub const CH2: Channel = Channel(2);
+    pub const CH3: Channel = Channel(3);
+    pub const CH4: Channel = Channel(4);
+    pub const CH5: Channel = Channel(5);
+    pub const CH6: Channel = Channel(6);
+    pub const CH7: Channel = Channel(7);
+
+    /// All channels in ascending order.
+    pub const ALL: [Channel; MAX_PORTS] = [
+        Self::CH0,
+        Self::CH1,
+        Self::CH2,
+        Self::CH3,
+        Self::CH4,
+        Self::CH5,
+        Self::CH6,
+        Self::CH7,
+    ];
+
+    /// Returns the channel with the given index, or `None` if it is out of range.
+    pub const fn new(index: u8) -> Option<Self> {
+        if index < MAX_PORTS as u8 {
+            Some(Self(index))
+        } else {
+            None
+        }
+    }
+
+    /// Index of the channel (0-7).
+    pub const fn index(self) -> u8 {
+        self.0
+    }
+
+    /// Control register bit selecting this channel.
+    pub const fn mask(self) -> u8 {
+        1 << self.0
+    }
+}
+
+impl From<Channel> for u8 {
+    fn from(channel: Channel) -> Self {
+        channel.0
+    }
+}
+
+/// Set of ports enabled in the control register.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PortSet(u8);
+
+impl PortSet {
+    /// Create a port set from the raw control register value.
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    /// Raw control register value.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns `true` if the given channel is enabled.
+    pub const fn is_enabled(self, channel: Channel) -> bool {
+        self.0 & channel.mask() != 0
+    }
+
+    /// Number of enabled ports.
+    pub const fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Returns `true` if no port is enabled.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterate over the enabled channels.
+    pub fn iter(self) -> impl Iterator<Item = Channel> {
+        Channel::ALL
+            .into_iter()
+            .filter(move |channel| self.is_enabled(*channel))
+    }
+
+    fn set(&mut self, channel: Channel, enabled: bool) {
+        if enabled {
+            self.0 |= channel.mask();
+        } else {
+            self.0 &= !channel.mask();
+        }
+    }
+}
+
+impl From<Channel> for PortSet {
+    fn from(channel: Channel) -> Self {
+        Self(channel.mask())
+    }
+}
+
+impl From<[bool; MAX_PORTS]> for PortSet {
+    fn from(states: [bool; MAX_PORTS]) -> Self {
+        let mut ports = PortSet::default();
+        for (channel, state) in Channel::ALL.into_iter().zip(states) {
+            ports.set(channel, state);
+        }
+        ports
+    }
+}
+
+impl From<PortSet> for [bool; MAX_PORTS] {
+    fn from(ports: PortSet) -> Self {
+        Channel::ALL.map(|channel| ports.is_enabled(channel))
+    }
+}
+
+/// Identifies a multiplexer within a `MuxTree`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MuxId(usize);
+
 #[derive(Debug)]
-pub struct PCA9548<I2C> {
+pub struct PCA9548<I2C, V = variant::Pca9548, RST = NoPin, DELAY = ()> {
     i2c: I2C,
     address: u8,
-    state: [bool; MAX_PORTS],
+    state: PortSet,
+    verify: bool,
+    devices: Option<DeviceMap>,
+    conflict_policy: ConflictPolicy,
+    conflict: Option<AddressConflict>,
+    recovery: Recovery,
+    reset_pin: RST,
+    delay: DELAY,
+    variant: PhantomData<V>,
 }
 
+/// 2-channel switch with interrupt logic
+pub type PCA9543<I2C, RST = NoPin, DELAY = ()> = PCA9548<I2C, variant::Pca9543, RST, DELAY>;
+/// 4-channel multiplexer with interrupt logic
+pub type PCA9544<I2C, RST = NoPin, DELAY = ()> = PCA9548<I2C, variant::Pca9544, RST, DELAY>;
+/// 4-channel switch with interrupt logic
+pub type PCA9545<I2C, RST = NoPin, DELAY = ()> = PCA9548<I2C, variant::Pca9545, RST, DELAY>;
+/// 4-channel switch
+pub type PCA9546<I2C, RST = NoPin, DELAY = ()> = PCA9548<I2C, variant::Pca9546, RST, DELAY>;
+/// 8-channel multiplexer
+pub type PCA9547<I2C, RST = NoPin, DELAY = ()> = PCA9548<I2C, variant::Pca9547, RST, DELAY>;
+
 pub(crate) fn address_from_pins(a0: bool, a1: bool, a2: bool) -> u8 {
-    let mut address = 0b1110_0000;
+    let mut address = DEFAULT_DEVICE_ADDRESS;
     if a0 {
         address |= 0b0000_0001;
     }
@@ -80,54 +280,149 @@ impl<I2C> PCA9548<I2C> {
         Self {
             i2c,
             address: DEFAULT_DEVICE_ADDRESS,
-            state: [false; MAX_PORTS],
+            state: PortSet::default(),
+            verify: false,
+            devices: None,
+            conflict_policy: ConflictPolicy::Refuse,
+            conflict: None,
+            recovery: Recovery::Report,
+            reset_pin: NoPin,
+            delay: (),
+            variant: PhantomData,
         }
     }
+}
 
-    /// Sets the address according to the enabled hardware settings
-    pub fn with_address_pins(mut self, a0: bool, a1: bool, a2: bool) -> Self {
-        self.address = address_from_pins(a0, a1, a2);
+impl<I2C, V, RST, DELAY> PCA9548<I2C, V, RST, DELAY>
+where
+    V: Variant,
+{
+    /// Drive another member of the PCA954x family, e.g. `with_variant::<Pca9546>()`.
+    ///
+    /// Returns `None` if the other part cannot be strapped to the address set
+    /// so far, see [`Variant::addresses`].
+    pub fn with_variant<V2>(self) -> Option<PCA9548<I2C, V2, RST, DELAY>>
+    where
+        V2: Variant,
+    {
+        if !V2::addresses().contains(&self.address) {
+            return None;
+        }
+        Some(PCA9548 {
+            i2c: self.i2c,
+            address: self.address,
+            state: PortSet::default(),
+            verify: self.verify,
+            devices: self.devices,
+            conflict_policy: self.conflict_policy,
+            conflict: self.conflict,
+            recovery: self.recovery,
+            reset_pin: self.reset_pin,
+            delay: self.delay,
+            variant: PhantomData,
+        })
+    }
+
+    /// Drive the active-low RESET input from `pin`, using `delay` to time the pulse.
+    pub fn with_reset_pin<RST2, DELAY2>(
+        self,
+        pin: RST2,
+        delay: DELAY2,
+    ) -> PCA9548<I2C, V, RST2, DELAY2> {
+        PCA9548 {
+            i2c: self.i2c,
+            address: self.address,
+            state: self.state,
+            verify: self.verify,
+            devices: self.devices,
+            conflict_policy: self.conflict_policy,
+            conflict: self.conflict,
+            recovery: self.recovery,
+            reset_pin: pin,
+            delay,
+            variant: PhantomData,
+        }
+    }
+
+    /// What channel guards do when a transaction fails with a bus lock-up,
+    /// [`Recovery::Report`] by default
+    ///
+    /// [`Recovery::ResetAndRetry`] needs a reset pin, see
+    /// [`with_reset_pin`](Self::with_reset_pin).
+    pub fn with_recovery(mut self, recovery: Recovery) -> Self {
+        self.recovery = recovery;
         self
     }
 
+    /// Sets the address according to the enabled hardware settings
+    ///
+    /// Returns `None` if a pin is set that the variant does not have.
+    pub fn with_address_pins(self, a0: bool, a1: bool, a2: bool) -> Option<Self> {
+        self.with_address(address_from_pins(a0, a1, a2))
+    }
+
     /// Create new instance of the PCA9548 device specifying the address.
-    pub fn with_address(mut self, address: u8) -> Self {
+    ///
+    /// Returns `None` if the variant cannot be strapped to `address`, see
+    /// [`Variant::addresses`].
+    pub fn with_address(mut self, address: u8) -> Option<Self> {
+        if !V::addresses().contains(&address) {
+            return None;
+        }
         self.address = address;
+        Some(self)
+    }
+
+    /// Read back the control register after every write and fail with
+    /// [`PCA9548Error::VerifyError`](error::PCA9548Error::VerifyError) if it does not match.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
         self
     }
 
-    fn port_code(states: [bool; MAX_PORTS]) -> u8 {
-        let mut code = 0;
-        if states[0] {
-            code |= 0b0000_0001;
-        }
-        if states[1] {
-            code |= 0b0000_0010;
-        }
-        if states[2] {
-            code |= 0b0000_0100;
-        }
-        if states[3] {
-            code |= 0b0000_1000;
-        }
-        if states[4] {
-            code |= 0b0001_0000;
-        }
-        if states[5] {
-            code |= 0b0010_0000;
-        }
-        if states[6] {
-            code |= 0b0100_0000;
-        }
-        if states[7] {
-            code |= 0b1000_0000;
-        }
+    /// Registers the devices on each channel, e.g. the result of a scan, so
+    /// that [`set_port`](Self::set_port) and [`set_ports`](Self::set_ports)
+    /// check for address conflicts before enabling ports
+    pub fn with_devices(mut self, devices: DeviceMap) -> Self {
+        self.devices = Some(devices);
+        self
+    }
 
-        code
+    /// What to do when a port selection would put two devices with the same
+    /// address on the bus, refused by default
+    pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Replaces the registered devices, `None` disables conflict checks
+    pub fn set_devices(&mut self, devices: Option<DeviceMap>) {
+        self.devices = devices;
+    }
+
+    /// Registered devices, if any
+    pub fn devices(&self) -> Option<&DeviceMap> {
+        self.devices.as_ref()
+    }
+
+    /// Returns and clears the last conflict let through by
+    /// [`ConflictPolicy::Warn`]
+    pub fn take_conflict(&mut self) -> Option<AddressConflict> {
+        self.conflict.take()
+    }
+
+    /// Port selection as last written to or read from the device.
+    pub fn ports(&self) -> PortSet {
+        self.state
     }
 
     /// Destroy driver instance, return I²C bus instance.
     pub fn destroy(self) -> I2C {
         self.i2c
     }
+
+    /// Destroy driver instance, return I²C bus, reset pin and delay instances.
+    pub fn release(self) -> (I2C, RST, DELAY) {
+        (self.i2c, self.reset_pin, self.delay)
+    }
 }
@@ -0,0 +1,211 @@
+//! Discovery of the channels exposed by the Linux `i2c-mux-pca954x` driver.
+//!
+//! When the kernel drives the multiplexer, every channel shows up as its own
+//! I²C adapter. The mux device directory `{bus}-{address:04x}` under
+//! `/sys/bus/i2c/devices` holds a `channel-N` link to the adapter `i2c-M` of
+//! each channel, which is then opened as `/dev/i2c-M`.
+//!
+//! ```no_run
+//! use pca9548::linux::SysfsMux;
+//! use pca9548::prelude::*;
+//!
+//! let mux = SysfsMux::find(1, 0x70).unwrap();
+//! let i2c = mux.open(Channel::CH3).unwrap();
+//! ```
+
+use crate::{Channel, MAX_PORTS};
+use linux_embedded_hal::i2cdev::linux::LinuxI2CError;
+use linux_embedded_hal::{I2CError, I2cdev};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+/// Directory the kernel lists I²C adapters and devices in
+pub const SYSFS_I2C_DEVICES: &str = "/sys/bus/i2c/devices";
+
+/// Adapters the kernel created for the channels of one multiplexer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SysfsMux {
+    bus: u32,
+    address: u8,
+    adapters: [Option<u32>; MAX_PORTS],
+}
+
+impl SysfsMux {
+    /// Finds the multiplexer at `address` on adapter `bus`, e.g. `/dev/i2c-1`
+    pub fn find(bus: u32, address: u8) -> io::Result<Self> {
+        Self::find_in(SYSFS_I2C_DEVICES, bus, address)
+    }
+
+    /// Finds the multiplexer at `address` on adapter `bus` below `root`
+    /// instead of the system sysfs tree
+    ///
+    /// Fails with [`ErrorKind::NotFound`] if there is no such device or the
+    /// device has no channels.
+    pub fn find_in(root: impl AsRef<Path>, bus: u32, address: u8) -> io::Result<Self> {
+        let device = root.as_ref().join(std::format!("{bus}-{address:04x}"));
+
+        let mut adapters = [None; MAX_PORTS];
+        for entry in fs::read_dir(&device)? {
+            let entry = entry?;
+            let Some(channel) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("channel-"))
+                .and_then(|index| index.parse().ok())
+                .and_then(Channel::new)
+            else {
+                continue;
+            };
+            let target = fs::read_link(entry.path())?;
+            adapters[channel.index() as usize] =
+                Some(adapter_number(&target).ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        std::format!("{} is not an I2C adapter", target.display()),
+                    )
+                })?);
+        }
+
+        if adapters.iter().all(Option::is_none) {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                std::format!("{} has no mux channels", device.display()),
+            ));
+        }
+        Ok(Self {
+            bus,
+            address,
+            adapters,
+        })
+    }
+
+    /// Adapter number of the parent bus
+    pub fn bus(&self) -> u32 {
+        self.bus
+    }
+
+    /// Address of the multiplexer on the parent bus
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Adapter number the kernel assigned to `channel`
+    pub fn adapter(&self, channel: Channel) -> Option<u32> {
+        self.adapters[channel.index() as usize]
+    }
+
+    /// Device node of `channel`, e.g. `/dev/i2c-30`
+    pub fn device_path(&self, channel: Channel) -> Option<PathBuf> {
+        self.adapter(channel)
+            .map(|adapter| PathBuf::from(std::format!("/dev/i2c-{adapter}")))
+    }
+
+    /// Channels exposed by the kernel with their adapter numbers
+    pub fn channels(&self) -> impl Iterator<Item = (Channel, u32)> + '_ {
+        Channel::ALL
+            .into_iter()
+            .filter_map(|channel| Some((channel, self.adapter(channel)?)))
+    }
+
+    /// Opens the I²C adapter of `channel`
+    pub fn open(&self, channel: Channel) -> Result<I2cdev, I2CError> {
+        let path = self.device_path(channel).ok_or_else(|| {
+            I2CError::from(LinuxI2CError::from(io::Error::new(
+                ErrorKind::NotFound,
+                std::format!("channel {} is not exposed", channel.index()),
+            )))
+        })?;
+        Ok(I2cdev::new(path)?)
+    }
+
+    /// Opens the I²C adapters of every exposed channel
+    pub fn open_all(&self) -> Result<Vec<(Channel, I2cdev)>, I2CError> {
+        self.channels()
+            .map(|(channel, _)| Ok((channel, self.open(channel)?)))
+            .collect()
+    }
+}
+
+/// Number of the adapter a `channel-N` link points to, `i2c-M` → `M`
+fn adapter_number(target: &Path) -> Option<u32> {
+    target
+        .file_name()?
+        .to_str()?
+        .strip_prefix("i2c-")?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Builds a fake sysfs tree in a temporary directory
+    struct FakeSysfs(PathBuf);
+
+    impl FakeSysfs {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir()
+                .join(std::format!("pca9548-sysfs-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self(root)
+        }
+
+        fn mux(&self, bus: u32, address: u8, channels: &[(u8, u32)]) -> &Self {
+            let device = self.0.join(std::format!("{bus}-{address:04x}"));
+            fs::create_dir_all(&device).unwrap();
+            fs::write(device.join("name"), "pca9548\n").unwrap();
+            for (channel, adapter) in channels {
+                let adapter = std::format!("i2c-{adapter}");
+                fs::create_dir_all(device.join(&adapter)).unwrap();
+                symlink(&adapter, device.join(std::format!("channel-{channel}"))).unwrap();
+            }
+            self
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn channel_adapters() {
+        let sysfs = FakeSysfs::new("channels");
+        let channels: Vec<_> = (0..8)
+            .map(|channel| (channel, 30 + channel as u32))
+            .collect();
+        sysfs.mux(1, 0x70, &channels).mux(1, 0x71, &[(2, 40)]);
+
+        let mux = SysfsMux::find_in(&sysfs.0, 1, 0x70).unwrap();
+        assert_eq!((mux.bus(), mux.address()), (1, 0x70));
+        assert_eq!(mux.adapter(Channel::CH0), Some(30));
+        assert_eq!(mux.adapter(Channel::CH7), Some(37));
+        assert_eq!(
+            mux.device_path(Channel::CH4),
+            Some(PathBuf::from("/dev/i2c-34"))
+        );
+        assert_eq!(mux.channels().count(), 8);
+
+        let mux = SysfsMux::find_in(&sysfs.0, 1, 0x71).unwrap();
+        assert_eq!(mux.channels().collect::<Vec<_>>(), [(Channel::CH2, 40)]);
+        assert_eq!(mux.device_path(Channel::CH0), None);
+    }
+
+    #[test]
+    fn missing_mux() {
+        let sysfs = FakeSysfs::new("missing");
+        sysfs.mux(1, 0x70, &[]);
+
+        let error = SysfsMux::find_in(&sysfs.0, 2, 0x70).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        // A plain device without channel links is not a multiplexer
+        let error = SysfsMux::find_in(&sysfs.0, 1, 0x70).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+}
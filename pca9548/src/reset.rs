@@ -0,0 +1,84 @@
+//! RESET input of the multiplexer.
+//!
+//! Pulsing RESET disables every channel and releases a bus held low by the
+//! multiplexer. Drivers given a reset pin can do so by themselves when a
+//! transaction fails with an error pointing to a bus lock-up, see
+//! [`needs_reset`](crate::error::needs_reset): [`PCA9548::with_recovery`]
+//! for channel guards, `with_reset_pin` on bus and tree ports. The pulse is
+//! only tried once per transaction, a second failure is reported as is.
+//!
+//! [`PCA9548::with_recovery`]: crate::PCA9548::with_recovery
+
+use crate::{RESET_PULSE_NS, RESET_RECOVERY_NS};
+#[cfg(not(feature = "async"))]
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{Error, ErrorKind, OutputPin};
+#[cfg(feature = "async")]
+use embedded_hal_async::delay::DelayNs as AsyncDelayNs;
+
+/// RESET input left unconnected
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NoPin;
+
+/// What a channel guard does when a transaction fails with an error that
+/// needs a reset
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Recovery {
+    /// Return the error, the caller decides whether to call
+    /// [`PCA9548::recover`](crate::PCA9548::recover)
+    #[default]
+    Report,
+    /// Pulse RESET, restore the port selection and retry the transaction once
+    ResetAndRetry,
+}
+
+/// Pin driving the RESET input, timed with `DELAY`
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "ResetPin",
+        idents(AsyncDelayNs(sync = "DelayNs"))
+    ),
+    async(feature = "async", keep_self)
+)]
+#[allow(async_fn_in_trait)]
+pub trait ResetPin<DELAY> {
+    /// Pulses RESET low, returns `false` if there is no pin to pulse
+    async fn pulse(&mut self, delay: &mut DELAY) -> Result<bool, ErrorKind>;
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "NoPin",
+        idents(AsyncDelayNs(sync = "DelayNs"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<DELAY> ResetPin<DELAY> for NoPin {
+    async fn pulse(&mut self, _delay: &mut DELAY) -> Result<bool, ErrorKind> {
+        Ok(false)
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "P",
+        idents(AsyncDelayNs(sync = "DelayNs"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<P, DELAY> ResetPin<DELAY> for P
+where
+    P: OutputPin,
+    DELAY: AsyncDelayNs,
+{
+    async fn pulse(&mut self, delay: &mut DELAY) -> Result<bool, ErrorKind> {
+        self.set_low().map_err(|error| error.kind())?;
+        delay.delay_ns(RESET_PULSE_NS).await;
+        self.set_high().map_err(|error| error.kind())?;
+        delay.delay_ns(RESET_RECOVERY_NS).await;
+        Ok(true)
+    }
+}
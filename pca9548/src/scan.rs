@@ -0,0 +1,162 @@
+//! Probing the channels of a multiplexer for devices.
+
+use crate::{Channel, MAX_PORTS, PortSet};
+#[cfg(not(feature = "async"))]
+use embedded_hal::i2c::I2c;
+use embedded_hal::i2c::{Error, ErrorKind};
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+/// First address probed, lower addresses are reserved by the I²C specification
+pub const FIRST_ADDRESS: u8 = 0x08;
+/// Last address probed, higher addresses are reserved by the I²C specification
+pub const LAST_ADDRESS: u8 = 0x77;
+
+/// Addresses found on each channel of a multiplexer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DeviceMap {
+    channels: [u128; MAX_PORTS],
+    upstream: u128,
+}
+
+impl DeviceMap {
+    pub const fn new() -> Self {
+        Self {
+            channels: [0; MAX_PORTS],
+            upstream: 0,
+        }
+    }
+
+    /// Records a device at `address` on `channel`
+    pub fn insert(&mut self, channel: Channel, address: u8) {
+        self.channels[channel.index() as usize] |= bit(address);
+    }
+
+    /// Records a device at `address` on the upstream bus
+    pub fn insert_upstream(&mut self, address: u8) {
+        self.upstream |= bit(address);
+    }
+
+    /// Returns `true` if a device at `address` was found on `channel`
+    pub fn contains(&self, channel: Channel, address: u8) -> bool {
+        self.channels[channel.index() as usize] & bit(address) != 0
+    }
+
+    /// Returns `true` if a device at `address` answers with all channels disabled
+    ///
+    /// Such a device sits on the upstream bus and collides with every channel.
+    pub fn is_upstream(&self, address: u8) -> bool {
+        self.upstream & bit(address) != 0
+    }
+
+    /// Addresses found on `channel`
+    pub fn devices(&self, channel: Channel) -> impl Iterator<Item = u8> {
+        addresses(self.channels[channel.index() as usize])
+    }
+
+    /// Addresses found on the upstream bus
+    pub fn upstream(&self) -> impl Iterator<Item = u8> {
+        addresses(self.upstream)
+    }
+
+    /// Channels on which a device at `address` was found
+    pub fn channels_with(&self, address: u8) -> PortSet {
+        let bits = Channel::ALL
+            .into_iter()
+            .filter(|channel| self.contains(*channel, address))
+            .fold(0, |bits, channel| bits | channel.mask());
+        PortSet::from_bits(bits)
+    }
+
+    /// Returns the first address used by more than one device on the bus
+    /// when `ports` are enabled, counting devices on the upstream bus
+    pub fn conflict(&self, ports: PortSet) -> Option<AddressConflict> {
+        let mut seen = self.upstream;
+        let mut clashes = 0;
+        for channel in ports.iter() {
+            let devices = self.channels[channel.index() as usize];
+            clashes |= seen & devices;
+            seen |= devices;
+        }
+
+        if clashes == 0 {
+            return None;
+        }
+        let address = clashes.trailing_zeros() as u8;
+        Some(AddressConflict {
+            address,
+            channels: PortSet::from_bits(self.channels_with(address).bits() & ports.bits()),
+            upstream: self.is_upstream(address),
+        })
+    }
+
+    pub(crate) fn from_probes(upstream: u128, channels: [u128; MAX_PORTS]) -> Self {
+        Self { channels, upstream }
+    }
+}
+
+/// Devices sharing an address on the bus at the same time
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AddressConflict {
+    /// Address used by more than one device
+    pub address: u8,
+    /// Enabled channels with a device at `address`
+    pub channels: PortSet,
+    /// `true` if a device at `address` also sits on the upstream bus
+    pub upstream: bool,
+}
+
+/// What to do when enabling ports would put two devices with the same
+/// address on the bus
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ConflictPolicy {
+    /// Fail with [`PCA9548Error::ConflictError`](crate::error::PCA9548Error::ConflictError)
+    /// and leave the port selection unchanged
+    #[default]
+    Refuse,
+    /// Enable the ports and record the conflict, see
+    /// [`PCA9548::take_conflict`](crate::PCA9548::take_conflict)
+    Warn,
+}
+
+fn bit(address: u8) -> u128 {
+    1 << (address & 0x7F)
+}
+
+fn addresses(bits: u128) -> impl Iterator<Item = u8> {
+    (0..128).filter(move |address| bits & bit(*address) != 0)
+}
+
+/// Probes every non-reserved address except `skip` and those in `exclude`,
+/// returning the addresses that acknowledged
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "probe_all",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+pub(crate) async fn probe_all<I2C>(
+    i2c: &mut I2C,
+    skip: u8,
+    exclude: u128,
+) -> Result<u128, I2C::Error>
+where
+    I2C: AsyncI2c,
+{
+    let mut found = 0;
+    for address in FIRST_ADDRESS..=LAST_ADDRESS {
+        if address == skip || exclude & bit(address) != 0 {
+            continue;
+        }
+
+        let mut buffer = [0u8; 1];
+        match i2c.read(address, &mut buffer).await {
+            Ok(()) => found |= bit(address),
+            Err(e) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(found)
+}
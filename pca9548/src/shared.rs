@@ -0,0 +1,306 @@
+//! Multiplexer shared between async tasks.
+//!
+//! [`SharedMultiplexer`] owns the upstream bus behind an `embassy-sync`
+//! mutex. Each [`SharedPort`] locks the bus, selects its channel if another
+//! port changed the selection and runs the transaction before unlocking, so
+//! tasks talking to different channels cannot interleave a select write with
+//! another task's transaction.
+
+use crate::error::Phase;
+use crate::prelude::PCA9548Error;
+use crate::variant::{Pca9548, Variant};
+use crate::{Channel, DEFAULT_DEVICE_ADDRESS, address_from_pins};
+use core::marker::PhantomData;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_hal::i2c::{ErrorType, Operation, SevenBitAddress};
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+struct Shared<I2C> {
+    i2c: I2C,
+    /// Control register value last written, `None` if unknown
+    selected: Option<u8>,
+}
+
+/// Multiplexer and upstream bus shared between tasks
+pub struct SharedMultiplexer<M: RawMutex, I2C, V = Pca9548> {
+    address: u8,
+    shared: Mutex<M, Shared<I2C>>,
+    variant: PhantomData<V>,
+}
+
+impl<M: RawMutex, I2C> SharedMultiplexer<M, I2C> {
+    /// Takes ownership of the upstream bus, e.g. to place the multiplexer in a
+    /// `static`
+    pub const fn new(i2c: I2C) -> Self {
+        Self {
+            address: DEFAULT_DEVICE_ADDRESS,
+            shared: Mutex::new(Shared {
+                i2c,
+                selected: None,
+            }),
+            variant: PhantomData,
+        }
+    }
+}
+
+impl<M, I2C, V> SharedMultiplexer<M, I2C, V>
+where
+    M: RawMutex,
+    V: Variant,
+{
+    /// Sets the address according to the enabled hardware settings
+    ///
+    /// Returns `None` if a pin is set that the variant does not have.
+    pub fn with_address_pins(self, a0: bool, a1: bool, a2: bool) -> Option<Self> {
+        self.with_address(address_from_pins(a0, a1, a2))
+    }
+
+    /// Sets the address
+    ///
+    /// Returns `None` if the variant cannot be strapped to `address`, see
+    /// [`Variant::addresses`].
+    pub fn with_address(mut self, address: u8) -> Option<Self> {
+        if !V::addresses().contains(&address) {
+            return None;
+        }
+        self.address = address;
+        Some(self)
+    }
+
+    /// Drives another member of the PCA954x family, e.g. `with_variant::<Pca9546>()`
+    ///
+    /// Returns `None` if the other part cannot be strapped to the address set
+    /// so far, see [`Variant::addresses`].
+    pub fn with_variant<V2>(self) -> Option<SharedMultiplexer<M, I2C, V2>>
+    where
+        V2: Variant,
+    {
+        if !V2::addresses().contains(&self.address) {
+            return None;
+        }
+        Some(SharedMultiplexer {
+            address: self.address,
+            shared: self.shared,
+            variant: PhantomData,
+        })
+    }
+
+    /// Creates a virtual I²C device talking to the given channel
+    ///
+    /// Transactions fail with [`PCA9548Error::PortError`] if the variant does
+    /// not have that channel.
+    pub fn new_port(&self, channel: Channel) -> SharedPort<'_, M, I2C, V> {
+        SharedPort {
+            mux: self,
+            channel,
+            code: V::encode(channel.into()),
+        }
+    }
+
+    /// Forgets the selected channel, e.g. after the multiplexer was reset
+    pub async fn invalidate(&self) {
+        self.shared.lock().await.selected = None;
+    }
+
+    /// Destroys the multiplexer, returning the upstream bus
+    pub fn into_inner(self) -> I2C {
+        self.shared.into_inner().i2c
+    }
+}
+
+/// Virtual I²C device behind one channel of a [`SharedMultiplexer`]
+pub struct SharedPort<'a, M: RawMutex, I2C, V = Pca9548> {
+    mux: &'a SharedMultiplexer<M, I2C, V>,
+    channel: Channel,
+    /// Control register value selecting the channel, `None` if the variant lacks it
+    code: Option<u8>,
+}
+
+impl<M, I2C, V> SharedPort<'_, M, I2C, V>
+where
+    M: RawMutex,
+    I2C: AsyncI2c,
+{
+    /// Channel this port talks to
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Locks the bus, selects the channel if needed and runs `operations`
+    async fn with_channel(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), PCA9548Error<I2C::Error>> {
+        let code = self.code.ok_or(PCA9548Error::PortError)?;
+        let mut shared = self.mux.shared.lock().await;
+
+        if shared.selected != Some(code) {
+            shared.selected = None;
+            shared
+                .i2c
+                .write(self.mux.address, &[code])
+                .await
+                .map_err(|error| PCA9548Error::i2c(Phase::Select, Some(self.channel), error))?;
+            shared.selected = Some(code);
+        }
+
+        shared
+            .i2c
+            .transaction(address, operations)
+            .await
+            .map_err(|error| PCA9548Error::i2c(Phase::Device, Some(self.channel), error))
+    }
+}
+
+impl<M, I2C, V> ErrorType for SharedPort<'_, M, I2C, V>
+where
+    M: RawMutex,
+    I2C: ErrorType,
+{
+    type Error = PCA9548Error<I2C::Error>;
+}
+
+impl<M, I2C, V> AsyncI2c for SharedPort<'_, M, I2C, V>
+where
+    M: RawMutex,
+    I2C: AsyncI2c,
+{
+    async fn read(&mut self, address: SevenBitAddress, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.with_channel(address, &mut [Operation::Read(read)])
+            .await
+    }
+
+    async fn write(&mut self, address: SevenBitAddress, write: &[u8]) -> Result<(), Self::Error> {
+        self.with_channel(address, &mut [Operation::Write(write)])
+            .await
+    }
+
+    async fn write_read(
+        &mut self,
+        address: SevenBitAddress,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.with_channel(
+            address,
+            &mut [Operation::Write(write), Operation::Read(read)],
+        )
+        .await
+    }
+
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.with_channel(address, operations).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate alloc;
+    use crate::prelude::*;
+    use alloc::vec;
+    use embassy_futures::{block_on, join::join};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embedded_hal::i2c::ErrorKind;
+    use embedded_hal_async::i2c::I2c;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    fn rejects_addresses_the_variant_lacks() {
+        let mut i2c = Mock::new(&[]);
+        let mux: SharedMultiplexer<NoopRawMutex, _> = SharedMultiplexer::new(&mut i2c);
+        let mux = mux.with_variant::<crate::variant::Pca9543>().unwrap();
+        assert!(mux.with_address(0x74).is_none());
+        i2c.done();
+    }
+
+    #[test]
+    fn tasks_select_their_channel() {
+        let expectations = [
+            Transaction::write(0x70, vec![0b0000_0001]),
+            Transaction::transaction_start(0x04),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::transaction_end(0x04),
+            Transaction::write(0x70, vec![0b0010_0000]),
+            Transaction::transaction_start(0x04),
+            Transaction::write(0x04, vec![0x02]),
+            Transaction::read(0x04, vec![0x03]),
+            Transaction::transaction_end(0x04),
+            Transaction::transaction_start(0x04),
+            Transaction::write(0x04, vec![0x04]),
+            Transaction::transaction_end(0x04),
+        ];
+        let mux: SharedMultiplexer<NoopRawMutex, _> =
+            SharedMultiplexer::new(Mock::new(&expectations));
+
+        let mut first = mux.new_port(Channel::CH0);
+        let mut second = mux.new_port(Channel::CH5);
+        block_on(async {
+            let mut data = [0];
+            let (a, b) = join(
+                first.write(0x04, &[0x01]),
+                second.write_read(0x04, &[0x02], &mut data),
+            )
+            .await;
+            assert!(a.is_ok() && b.is_ok());
+            assert_eq!(data, [0x03]);
+            // Channel 5 is still selected
+            second.write(0x04, &[0x04]).await.unwrap();
+        });
+
+        mux.into_inner().done();
+    }
+
+    #[test]
+    fn failed_select_is_retried() {
+        let expectations = [
+            Transaction::write(0x71, vec![0b0000_0100]).with_error(ErrorKind::Other),
+            Transaction::write(0x71, vec![0b0000_0100]),
+            Transaction::transaction_start(0x04),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::transaction_end(0x04),
+        ];
+        let mux: SharedMultiplexer<NoopRawMutex, _> =
+            SharedMultiplexer::new(Mock::new(&expectations))
+                .with_address(0x71)
+                .unwrap();
+
+        let mut port = mux.new_port(Channel::CH2);
+        block_on(async {
+            assert_eq!(
+                port.write(0x04, &[0x01]).await,
+                Err(PCA9548Error::I2CError {
+                    mux: None,
+                    channel: Some(Channel::CH2),
+                    phase: Phase::Select,
+                    error: ErrorKind::Other
+                })
+            );
+            assert!(port.write(0x04, &[0x01]).await.is_ok());
+        });
+
+        mux.into_inner().done();
+    }
+
+    #[test]
+    fn encoded_channels() {
+        let mux = SharedMultiplexer::<NoopRawMutex, _>::new(Mock::new(&[]))
+            .with_variant::<crate::variant::Pca9544>()
+            .unwrap();
+
+        let mut port = mux.new_port(Channel::CH4);
+        block_on(async {
+            assert_eq!(
+                port.write(0x04, &[0x01]).await,
+                Err(PCA9548Error::PortError)
+            );
+        });
+
+        mux.into_inner().done();
+    }
+}
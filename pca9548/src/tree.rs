@@ -0,0 +1,693 @@
+//! Cascaded multiplexers, where a multiplexer hangs off a channel of another one.
+//!
+//! A [`MuxTree`] describes the topology and hands out [`TreePort`]s, virtual
+//! I²C devices that select the whole path from the upstream bus to one leaf
+//! channel. Only the levels whose selection changed are rewritten, and any
+//! other multiplexer sharing a segment of the path is disabled so that devices
+//! behind sibling branches can never appear on the bus at the same time.
+
+pub use crate::MuxId;
+
+use crate::Channel;
+use crate::cache::{RefCellCache, SelectionCache};
+use crate::error::{Phase, needs_reset};
+use crate::prelude::PCA9548Error;
+use crate::reset::{NoPin, ResetPin};
+use crate::variant::{Encoding, Pca9548, Variant};
+#[cfg(not(feature = "async"))]
+use embedded_hal::i2c::I2c;
+use embedded_hal::i2c::{Error, ErrorType, Operation, SevenBitAddress};
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct MuxNode {
+    address: u8,
+    /// Multiplexer and channel this one is connected to, `None` for the upstream bus
+    parent: Option<(MuxId, Channel)>,
+    channels: u8,
+    encoding: Encoding,
+}
+
+impl MuxNode {
+    /// Returns `None` if the part cannot be strapped to `address`
+    fn new<V: Variant>(address: u8, parent: Option<(MuxId, Channel)>) -> Option<Self> {
+        if !V::addresses().contains(&address) {
+            return None;
+        }
+        Some(Self {
+            address,
+            parent,
+            channels: V::CHANNELS,
+            encoding: V::ENCODING,
+        })
+    }
+}
+
+/// Tree of up to `N` multiplexers
+pub struct MuxTree<const N: usize, C = RefCellCache> {
+    nodes: [Option<MuxNode>; N],
+    selected: [C; N],
+}
+
+impl<const N: usize, C> MuxTree<N, C>
+where
+    C: SelectionCache + Default,
+{
+    pub fn new() -> Self {
+        Self {
+            nodes: [None; N],
+            selected: core::array::from_fn(|_| C::default()),
+        }
+    }
+}
+
+impl<const N: usize, C> Default for MuxTree<N, C>
+where
+    C: SelectionCache + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, C> MuxTree<N, C>
+where
+    C: SelectionCache,
+{
+    /// Adds a PCA9548 connected to the upstream bus
+    ///
+    /// Returns `None` if the tree is full, the address is already used on the
+    /// upstream bus or the part cannot be strapped to it.
+    pub fn add_root(&mut self, address: u8) -> Option<MuxId> {
+        self.add_root_variant::<Pca9548>(address)
+    }
+
+    /// Adds a member of the PCA954x family connected to the upstream bus
+    pub fn add_root_variant<V: Variant>(&mut self, address: u8) -> Option<MuxId> {
+        self.add_node(MuxNode::new::<V>(address, None)?)
+    }
+
+    /// Adds a PCA9548 connected to `channel` of the `parent` multiplexer
+    ///
+    /// Returns `None` if the tree is full, the parent is unknown or lacks that
+    /// channel, the address is already used on that channel or on any segment
+    /// between it and the upstream bus, or the part cannot be strapped to it.
+    pub fn add_mux(&mut self, parent: MuxId, channel: Channel, address: u8) -> Option<MuxId> {
+        self.add_mux_variant::<Pca9548>(parent, channel, address)
+    }
+
+    /// Adds a member of the PCA954x family connected to `channel` of the
+    /// `parent` multiplexer
+    pub fn add_mux_variant<V: Variant>(
+        &mut self,
+        parent: MuxId,
+        channel: Channel,
+        address: u8,
+    ) -> Option<MuxId> {
+        if channel.index() >= self.node(parent)?.channels {
+            return None;
+        }
+        self.add_node(MuxNode::new::<V>(address, Some((parent, channel)))?)
+    }
+
+    /// Creates a virtual I²C device talking to `channel` of the `mux` multiplexer
+    pub fn new_port<I2C>(&self, i2c: I2C, mux: MuxId, channel: Channel) -> TreePort<'_, I2C, N, C> {
+        TreePort {
+            bus: i2c,
+            tree: self,
+            mux,
+            channel,
+            reset_pin: NoPin,
+            delay: (),
+        }
+    }
+
+    /// Forgets the cached selection of every multiplexer, e.g. after a reset
+    pub fn invalidate(&self) {
+        for selected in &self.selected {
+            selected.set_selected(None);
+        }
+    }
+
+    fn add_node(&mut self, node: MuxNode) -> Option<MuxId> {
+        // Every multiplexer on a segment between the upstream bus and the new
+        // one sees its select writes, including its ancestors
+        let mut segment = node.parent;
+        loop {
+            if self
+                .attached(segment)
+                .filter_map(|other| self.node(other))
+                .any(|other| other.address == node.address)
+            {
+                return None;
+            }
+            let Some((parent, _)) = segment else {
+                break;
+            };
+            segment = self.node(parent)?.parent;
+        }
+
+        let index = self.nodes.iter().position(Option::is_none)?;
+        self.nodes[index] = Some(node);
+        Some(MuxId(index))
+    }
+
+    fn node(&self, id: MuxId) -> Option<MuxNode> {
+        self.nodes.get(id.0).copied().flatten()
+    }
+
+    /// Multiplexers attached to the given segment, `None` being the upstream bus
+    fn attached(&self, segment: Option<(MuxId, Channel)>) -> impl Iterator<Item = MuxId> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(move |(_, node)| node.is_some_and(|node| node.parent == segment))
+            .map(|(index, _)| MuxId(index))
+    }
+}
+
+/// Virtual I²C device behind one leaf channel of a [`MuxTree`]
+///
+/// Errors name the multiplexer and channel of the level that failed and
+/// whether it failed selecting the path, disabling a multiplexer off the
+/// path or talking to the device, see [`PCA9548Error::I2CError`].
+pub struct TreePort<'a, I2C, const N: usize, C = RefCellCache, RST = NoPin, DELAY = ()> {
+    bus: I2C,
+    tree: &'a MuxTree<N, C>,
+    mux: MuxId,
+    channel: Channel,
+    reset_pin: RST,
+    delay: DELAY,
+}
+
+impl<'a, I2C, const N: usize, C, RST, DELAY> TreePort<'a, I2C, N, C, RST, DELAY> {
+    /// Pulses `pin`, timed with `delay`, to reset the multiplexers when a
+    /// transaction fails with a bus lock-up, then selects the path again and
+    /// retries the transaction once
+    ///
+    /// The pin is expected to reset every multiplexer of the tree, the cached
+    /// selection of all of them is forgotten.
+    pub fn with_reset_pin<RST2, DELAY2>(
+        self,
+        pin: RST2,
+        delay: DELAY2,
+    ) -> TreePort<'a, I2C, N, C, RST2, DELAY2> {
+        TreePort {
+            bus: self.bus,
+            tree: self.tree,
+            mux: self.mux,
+            channel: self.channel,
+            reset_pin: pin,
+            delay,
+        }
+    }
+
+    /// Multiplexer and channel this port talks to
+    pub fn leaf(&self) -> (MuxId, Channel) {
+        (self.mux, self.channel)
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "TreePort",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, const N: usize, C, RST, DELAY> TreePort<'_, I2C, N, C, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    C: SelectionCache,
+{
+    /// Selects every level between the upstream bus and the leaf channel
+    async fn open_path(&mut self) -> Result<(), PCA9548Error<I2C::Error>> {
+        let mut path = [(self.mux, self.channel); N];
+        let mut depth = 0;
+        let mut level = Some((self.mux, self.channel));
+        while let Some((mux, channel)) = level {
+            let node = self.tree.node(mux).ok_or(PCA9548Error::PortError)?;
+            path[depth] = (mux, channel);
+            depth += 1;
+            level = node.parent;
+        }
+
+        let mut segment = None;
+        for &(mux, channel) in path[..depth].iter().rev() {
+            for sibling in self
+                .tree
+                .attached(segment)
+                .filter(|sibling| *sibling != mux)
+            {
+                self.select(sibling, None, 0).await?;
+            }
+            let node = self.tree.node(mux).ok_or(PCA9548Error::PortError)?;
+            let code = node
+                .encoding
+                .encode(node.channels, channel.into())
+                .ok_or(PCA9548Error::PortError)?;
+            self.select(mux, Some(channel), code).await?;
+            segment = Some((mux, channel));
+        }
+        for child in self.tree.attached(segment) {
+            self.select(child, None, 0).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the control register of `mux`, selecting `channel` or, if
+    /// `None`, disabling a multiplexer off the path
+    async fn select(
+        &mut self,
+        mux: MuxId,
+        channel: Option<Channel>,
+        code: u8,
+    ) -> Result<(), PCA9548Error<I2C::Error>> {
+        let selected = &self.tree.selected[mux.0];
+        if selected.selected() == Some(code) {
+            return Ok(());
+        }
+
+        let address = self.tree.node(mux).ok_or(PCA9548Error::PortError)?.address;
+        match self.bus.write(address, &[code]).await {
+            Ok(()) => {
+                selected.set_selected(Some(code));
+                Ok(())
+            }
+            Err(error) => {
+                selected.set_selected(None);
+                Err(PCA9548Error::I2CError {
+                    mux: Some(mux),
+                    channel,
+                    phase: match channel {
+                        Some(_) => Phase::Select,
+                        None => Phase::DisableSibling,
+                    },
+                    error,
+                })
+            }
+        }
+    }
+
+    fn device_error(&self, error: I2C::Error) -> PCA9548Error<I2C::Error> {
+        PCA9548Error::I2CError {
+            mux: Some(self.mux),
+            channel: Some(self.channel),
+            phase: Phase::Device,
+            error,
+        }
+    }
+}
+
+impl<I2C, const N: usize, C, RST, DELAY> ErrorType for TreePort<'_, I2C, N, C, RST, DELAY>
+where
+    I2C: ErrorType,
+{
+    type Error = PCA9548Error<I2C::Error>;
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "TreePort",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, const N: usize, C, RST, DELAY> TreePort<'_, I2C, N, C, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    C: SelectionCache,
+    RST: ResetPin<DELAY>,
+{
+    /// Resets the multiplexers after a bus lock-up and selects the path
+    /// again, returns `true` if the transaction should be retried
+    async fn recover(
+        &mut self,
+        result: &Result<(), I2C::Error>,
+    ) -> Result<bool, PCA9548Error<I2C::Error>> {
+        let Err(error) = result else {
+            return Ok(false);
+        };
+        if !needs_reset(error.kind()) {
+            return Ok(false);
+        }
+        let reset = self
+            .reset_pin
+            .pulse(&mut self.delay)
+            .await
+            .map_err(|_| PCA9548Error::PinError)?;
+        if !reset {
+            return Ok(false);
+        }
+        self.tree.invalidate();
+        self.open_path().await?;
+        Ok(true)
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "TreePort",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, const N: usize, C, RST, DELAY> AsyncI2c for TreePort<'_, I2C, N, C, RST, DELAY>
+where
+    I2C: AsyncI2c,
+    C: SelectionCache,
+    RST: ResetPin<DELAY>,
+{
+    async fn read(&mut self, address: SevenBitAddress, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.open_path().await?;
+        let mut result = self.bus.read(address, read).await;
+        if self.recover(&result).await? {
+            result = self.bus.read(address, read).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+
+    async fn write(&mut self, address: SevenBitAddress, write: &[u8]) -> Result<(), Self::Error> {
+        self.open_path().await?;
+        let mut result = self.bus.write(address, write).await;
+        if self.recover(&result).await? {
+            result = self.bus.write(address, write).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+
+    async fn write_read(
+        &mut self,
+        address: SevenBitAddress,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.open_path().await?;
+        let mut result = self.bus.write_read(address, write, read).await;
+        if self.recover(&result).await? {
+            result = self.bus.write_read(address, write, read).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.open_path().await?;
+        let mut result = self.bus.transaction(address, operations).await;
+        if self.recover(&result).await? {
+            result = self.bus.transaction(address, operations).await;
+        }
+        result.map_err(|error| self.device_error(error))
+    }
+}
+
+#[cfg(all(test, not(feature = "async")))]
+mod test {
+    extern crate alloc;
+    use crate::prelude::*;
+    use crate::tree::MuxTree;
+    use alloc::vec;
+    use core::cell::RefCell;
+    use embedded_hal::i2c::I2c;
+    use embedded_hal_bus::i2c::RefCellDevice;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    fn topology() {
+        let mut tree = MuxTree::<2>::new();
+        let root = tree.add_root(0x70).unwrap();
+        assert_eq!(tree.add_root(0x70), None);
+        assert!(tree.add_mux(root, Channel::CH5, 0x71).is_some());
+        assert_eq!(tree.add_mux(root, Channel::CH4, 0x72), None);
+    }
+
+    #[test]
+    fn rejects_addresses_the_variant_lacks() {
+        use crate::variant::Pca9545;
+
+        let mut tree = MuxTree::<2>::new();
+        assert_eq!(tree.add_root(0x78), None);
+        assert_eq!(tree.add_root_variant::<Pca9545>(0x74), None);
+        let root = tree.add_root_variant::<Pca9545>(0x73).unwrap();
+        assert_eq!(
+            tree.add_mux_variant::<Pca9545>(root, Channel::CH0, 0x77),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_addresses_on_path() {
+        let mut tree = MuxTree::<4>::new();
+        let root = tree.add_root(0x70).unwrap();
+        let other_root = tree.add_root(0x74).unwrap();
+        // Both would ACK every select write of the root
+        assert_eq!(tree.add_mux(root, Channel::CH5, 0x70), None);
+        // The other root stays connected whatever the root selects
+        assert_eq!(tree.add_mux(root, Channel::CH5, 0x74), None);
+
+        let child = tree.add_mux(root, Channel::CH5, 0x71).unwrap();
+        assert_eq!(tree.add_mux(child, Channel::CH0, 0x70), None);
+        assert_eq!(tree.add_mux(child, Channel::CH0, 0x71), None);
+        // Sibling branches are never connected at the same time
+        assert!(tree.add_mux(other_root, Channel::CH0, 0x71).is_some());
+    }
+
+    #[test]
+    fn selects_changed_levels_only() {
+        let root_addr = 0x70;
+        let left_addr = 0x71;
+        let right_addr = 0x72;
+        let component_addr = 0x04;
+
+        let expectations = [
+            // Leaf A: left mux channel 1, the right mux shares root channel 5
+            Transaction::write(root_addr, vec![0b0010_0000]),
+            Transaction::write(right_addr, vec![0b0000_0000]),
+            Transaction::write(left_addr, vec![0b0000_0010]),
+            Transaction::write(component_addr, vec![0x01]),
+            Transaction::write(component_addr, vec![0x02]),
+            // Leaf B: left mux channel 6
+            Transaction::write(left_addr, vec![0b0100_0000]),
+            Transaction::write(component_addr, vec![0x03]),
+            // Leaf C: right mux channel 0, the left mux must be disabled first
+            Transaction::write(left_addr, vec![0b0000_0000]),
+            Transaction::write(right_addr, vec![0b0000_0001]),
+            Transaction::write(component_addr, vec![0x04]),
+            // Leaf D: directly on root channel 2
+            Transaction::write(root_addr, vec![0b0000_0100]),
+            Transaction::write(component_addr, vec![0x05]),
+            // Back to leaf C, only the root changes
+            Transaction::write(root_addr, vec![0b0010_0000]),
+            Transaction::write(component_addr, vec![0x06]),
+        ];
+
+        let i2c = RefCell::new(Mock::new(&expectations));
+        let mut tree = MuxTree::<3>::new();
+        let root = tree.add_root(root_addr).unwrap();
+        let left = tree.add_mux(root, Channel::CH5, left_addr).unwrap();
+        let right = tree.add_mux(root, Channel::CH5, right_addr).unwrap();
+
+        {
+            let mut leaf_a = tree.new_port(RefCellDevice::new(&i2c), left, Channel::CH1);
+            let mut leaf_b = tree.new_port(RefCellDevice::new(&i2c), left, Channel::CH6);
+            let mut leaf_c = tree.new_port(RefCellDevice::new(&i2c), right, Channel::CH0);
+            let mut leaf_d = tree.new_port(RefCellDevice::new(&i2c), root, Channel::CH2);
+
+            assert!(leaf_a.write(component_addr, &[0x01]).is_ok());
+            assert!(leaf_a.write(component_addr, &[0x02]).is_ok());
+            assert!(leaf_b.write(component_addr, &[0x03]).is_ok());
+            assert!(leaf_c.write(component_addr, &[0x04]).is_ok());
+            assert!(leaf_d.write(component_addr, &[0x05]).is_ok());
+            assert!(leaf_c.write(component_addr, &[0x06]).is_ok());
+        }
+
+        i2c.into_inner().done();
+    }
+
+    #[test]
+    fn errors_name_the_failing_level() {
+        use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+
+        let nack = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+        let expectations = [
+            Transaction::write(0x70, vec![0b0010_0000]),
+            Transaction::write(0x71, vec![0b0000_0000]).with_error(nack),
+            Transaction::write(0x71, vec![0b0000_0000]),
+            Transaction::write(0x72, vec![0b0000_0001]).with_error(nack),
+            Transaction::write(0x72, vec![0b0000_0001]),
+            Transaction::write(0x04, vec![0x01]).with_error(nack),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let mut tree = MuxTree::<3>::new();
+        let root = tree.add_root(0x70).unwrap();
+        let left = tree.add_mux(root, Channel::CH5, 0x71).unwrap();
+        let right = tree.add_mux(root, Channel::CH5, 0x72).unwrap();
+
+        {
+            let mut port = tree.new_port(&mut i2c, right, Channel::CH0);
+            assert_eq!(
+                port.write(0x04, &[0x01]),
+                Err(PCA9548Error::I2CError {
+                    mux: Some(left),
+                    channel: None,
+                    phase: Phase::DisableSibling,
+                    error: nack
+                })
+            );
+            assert_eq!(
+                port.write(0x04, &[0x01]),
+                Err(PCA9548Error::I2CError {
+                    mux: Some(right),
+                    channel: Some(Channel::CH0),
+                    phase: Phase::Select,
+                    error: nack
+                })
+            );
+            let error = port.write(0x04, &[0x01]).unwrap_err();
+            assert_eq!(error.mux(), Some(right));
+            assert_eq!(error.channel(), Some(Channel::CH0));
+            assert_eq!(error.phase(), Some(Phase::Device));
+        }
+
+        i2c.done();
+    }
+
+    #[test]
+    fn mixed_variants() {
+        use crate::variant::{Pca9544, Pca9546};
+
+        let expectations = [
+            Transaction::write(0x70, vec![0b0000_0010]),
+            Transaction::write(0x71, vec![0b0000_0111]),
+            Transaction::write(0x04, vec![0x01]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let mut tree = MuxTree::<2>::new();
+        let root = tree.add_root_variant::<Pca9546>(0x70).unwrap();
+        assert_eq!(tree.add_mux(root, Channel::CH4, 0x71), None);
+        let child = tree
+            .add_mux_variant::<Pca9544>(root, Channel::CH1, 0x71)
+            .unwrap();
+
+        {
+            let mut port = tree.new_port(&mut i2c, child, Channel::CH3);
+            assert!(port.write(0x04, &[0x01]).is_ok());
+        }
+
+        i2c.done();
+    }
+
+    #[test]
+    fn reselects_path_after_lock_up() {
+        use embedded_hal::i2c::ErrorKind;
+        use embedded_hal_mock::eh1::delay::{CheckedDelay, Transaction as DelayTransaction};
+        use embedded_hal_mock::eh1::digital::{
+            Mock as PinMock, State, Transaction as PinTransaction,
+        };
+
+        let expectations = [
+            Transaction::write(0x70, vec![0b0010_0000]),
+            Transaction::write(0x71, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]).with_error(ErrorKind::Bus),
+            // Both levels were reset
+            Transaction::write(0x70, vec![0b0010_0000]),
+            Transaction::write(0x71, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let mut pin = PinMock::new(&[
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let mut delay = CheckedDelay::new(&[
+            DelayTransaction::delay_ns(6),
+            DelayTransaction::delay_ns(500),
+        ]);
+        let mut tree = MuxTree::<2>::new();
+        let root = tree.add_root(0x70).unwrap();
+        let child = tree.add_mux(root, Channel::CH5, 0x71).unwrap();
+
+        {
+            let mut port = tree
+                .new_port(&mut i2c, child, Channel::CH1)
+                .with_reset_pin(&mut pin, &mut delay);
+            assert!(port.write(0x04, &[0x01]).is_ok());
+        }
+
+        i2c.done();
+        pin.done();
+        delay.done();
+    }
+
+    #[test]
+    fn disables_muxes_behind_leaf() {
+        let root_addr = 0x70;
+        let child_addr = 0x71;
+        let component_addr = 0x04;
+
+        let expectations = [
+            Transaction::write(root_addr, vec![0b0000_1000]),
+            Transaction::write(child_addr, vec![0b0000_0000]),
+            Transaction::read(component_addr, vec![0xAA]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let mut tree = MuxTree::<2>::new();
+        let root = tree.add_root(root_addr).unwrap();
+        tree.add_mux(root, Channel::CH3, child_addr).unwrap();
+
+        {
+            let mut port = tree.new_port(&mut i2c, root, Channel::CH3);
+            let mut data = [0];
+            assert!(port.read(component_addr, &mut data).is_ok());
+            assert_eq!(data, [0xAA]);
+        }
+
+        i2c.done();
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test_async {
+    extern crate alloc;
+    use crate::prelude::*;
+    use crate::tree::MuxTree;
+    use alloc::vec;
+    use embassy_futures::block_on;
+    use embedded_hal_async::i2c::I2c;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    #[test]
+    fn selects_path() {
+        let expectations = [
+            Transaction::write(0x70, vec![0b0010_0000]),
+            Transaction::write(0x71, vec![0b0000_0010]),
+            Transaction::write(0x04, vec![0x01]),
+            Transaction::write(0x04, vec![0x02]),
+        ];
+
+        let mut i2c = Mock::new(&expectations);
+        let mut tree = MuxTree::<2>::new();
+        let root = tree.add_root(0x70).unwrap();
+        let child = tree.add_mux(root, Channel::CH5, 0x71).unwrap();
+
+        block_on(async {
+            let mut port = tree.new_port(&mut i2c, child, Channel::CH1);
+            assert!(port.write(0x04, &[0x01]).await.is_ok());
+            assert!(port.write(0x04, &[0x02]).await.is_ok());
+        });
+
+        i2c.done();
+    }
+}
@@ -0,0 +1,185 @@
+//! Members of the PCA954x family of I²C switches and multiplexers.
+//!
+//! Switches (PCA9543, PCA9545, PCA9546, PCA9548) have one control register
+//! bit per channel and may enable any combination of channels. Multiplexers
+//! (PCA9544, PCA9547) select a single channel through an encoded channel
+//! field plus an enable bit.
+
+use crate::{DEFAULT_DEVICE_ADDRESS, PortSet};
+use core::ops::RangeInclusive;
+
+/// Encoding of the channel selection in the control register
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// One bit per channel, any combination of channels may be enabled
+    Mask,
+    /// Channel index plus an enable bit, at most one channel may be enabled
+    Indexed,
+}
+
+impl Encoding {
+    /// Control register value enabling `ports` on a part with `channels`
+    /// channels, or `None` if the combination is invalid
+    pub const fn encode(self, channels: u8, ports: PortSet) -> Option<u8> {
+        let bits = ports.bits();
+        if channels < 8 && bits >> channels != 0 {
+            return None;
+        }
+
+        match self {
+            Encoding::Mask => Some(bits),
+            Encoding::Indexed if bits == 0 => Some(0),
+            Encoding::Indexed if bits.is_power_of_two() => {
+                Some(channels | bits.trailing_zeros() as u8)
+            }
+            Encoding::Indexed => None,
+        }
+    }
+
+    /// Channels enabled by the control register value `code` on a part with
+    /// `channels` channels
+    pub const fn decode(self, channels: u8, code: u8) -> PortSet {
+        match self {
+            Encoding::Mask => PortSet::from_bits(code & channel_mask(channels)),
+            Encoding::Indexed if code & channels != 0 => {
+                PortSet::from_bits(1 << (code & (channels - 1)))
+            }
+            Encoding::Indexed => PortSet::from_bits(0),
+        }
+    }
+}
+
+const fn channel_mask(channels: u8) -> u8 {
+    if channels >= 8 {
+        u8::MAX
+    } else {
+        (1 << channels) - 1
+    }
+}
+
+/// Static description of a member of the family
+pub trait Variant {
+    /// Number of downstream channels
+    const CHANNELS: u8;
+    /// Encoding of the channel selection in the control register
+    const ENCODING: Encoding;
+    /// Number of hardware address pins, starting from A0
+    const ADDRESS_PINS: u8;
+
+    /// Addresses the part can be strapped to
+    fn addresses() -> RangeInclusive<u8> {
+        DEFAULT_DEVICE_ADDRESS..=DEFAULT_DEVICE_ADDRESS + ((1 << Self::ADDRESS_PINS) - 1)
+    }
+
+    /// Every channel of the part
+    fn all_ports() -> PortSet {
+        PortSet::from_bits(channel_mask(Self::CHANNELS))
+    }
+
+    /// Control register value enabling `ports`, or `None` if the part cannot
+    /// enable that combination
+    fn encode(ports: PortSet) -> Option<u8> {
+        Self::ENCODING.encode(Self::CHANNELS, ports)
+    }
+
+    /// Channels enabled by the control register value `code`
+    fn decode(code: u8) -> PortSet {
+        Self::ENCODING.decode(Self::CHANNELS, code)
+    }
+}
+
+/// Parts reporting the INT inputs of their channels in the upper nibble of
+/// the control register
+pub trait Interrupts: Variant {
+    /// Channels whose INT input is asserted according to the control register value `code`
+    fn interrupts(code: u8) -> PortSet {
+        PortSet::from_bits((code >> 4) & Self::all_ports().bits())
+    }
+}
+
+macro_rules! variant {
+    ($name:ident, $doc:literal, $channels:literal, $encoding:ident, $pins:literal) => {
+        #[doc = $doc]
+        #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name;
+
+        impl Variant for $name {
+            const CHANNELS: u8 = $channels;
+            const ENCODING: Encoding = Encoding::$encoding;
+            const ADDRESS_PINS: u8 = $pins;
+        }
+    };
+}
+
+variant!(Pca9543, "2-channel switch with interrupt logic", 2, Mask, 2);
+variant!(
+    Pca9544,
+    "4-channel multiplexer with interrupt logic",
+    4,
+    Indexed,
+    3
+);
+variant!(Pca9545, "4-channel switch with interrupt logic", 4, Mask, 2);
+variant!(Pca9546, "4-channel switch", 4, Mask, 3);
+variant!(Pca9547, "8-channel multiplexer", 8, Indexed, 3);
+variant!(Pca9548, "8-channel switch", 8, Mask, 3);
+
+impl Interrupts for Pca9543 {}
+impl Interrupts for Pca9544 {}
+impl Interrupts for Pca9545 {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Channel;
+    use rstest::*;
+
+    #[rstest]
+    #[case(0b0000_0000, Some(0b0000_0000))]
+    #[case(0b0000_0001, Some(0b0000_0100))]
+    #[case(0b0000_1000, Some(0b0000_0111))]
+    #[case(0b0000_0011, None)]
+    #[case(0b0001_0000, None)]
+    fn pca9544_encoding(#[case] ports: u8, #[case] code: Option<u8>) {
+        assert_eq!(Pca9544::encode(PortSet::from_bits(ports)), code);
+        if let Some(code) = code {
+            assert_eq!(Pca9544::decode(code), PortSet::from_bits(ports));
+        }
+    }
+
+    #[rstest]
+    #[case(0b0000_0000, Some(0b0000_0000))]
+    #[case(0b0000_0001, Some(0b0000_1000))]
+    #[case(0b1000_0000, Some(0b0000_1111))]
+    #[case(0b1000_0001, None)]
+    fn pca9547_encoding(#[case] ports: u8, #[case] code: Option<u8>) {
+        assert_eq!(Pca9547::encode(PortSet::from_bits(ports)), code);
+        if let Some(code) = code {
+            assert_eq!(Pca9547::decode(code), PortSet::from_bits(ports));
+        }
+    }
+
+    #[test]
+    fn switch_encoding() {
+        assert_eq!(Pca9548::encode(Pca9548::all_ports()), Some(0xFF));
+        assert_eq!(Pca9546::encode(Pca9546::all_ports()), Some(0x0F));
+        assert_eq!(Pca9546::encode(Channel::CH4.into()), None);
+        assert_eq!(Pca9543::encode(Channel::CH2.into()), None);
+        // Interrupt flags in the upper nibble are not channels
+        assert_eq!(Pca9545::decode(0b1010_0011), PortSet::from_bits(0b0011));
+    }
+
+    #[test]
+    fn interrupt_flags() {
+        assert_eq!(Pca9545::interrupts(0b1010_0011), PortSet::from_bits(0b1010));
+        assert_eq!(Pca9544::interrupts(0b0001_0101), PortSet::from_bits(0b0001));
+        assert_eq!(Pca9543::interrupts(0b1110_0001), PortSet::from_bits(0b0010));
+    }
+
+    #[test]
+    fn address_ranges() {
+        assert_eq!(Pca9548::addresses(), 0x70..=0x77);
+        assert_eq!(Pca9545::addresses(), 0x70..=0x73);
+        assert_eq!(Pca9543::addresses(), 0x70..=0x73);
+    }
+}
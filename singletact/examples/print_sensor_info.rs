@@ -1,9 +1,15 @@
-use linux_embedded_hal::I2cdev;
-use singletact::{DEFAULT_DEVICE_ADDRESS, SingleTact};
-
+#[cfg(not(feature = "async"))]
 fn main() {
+    use linux_embedded_hal::I2cdev;
+    use singletact::{DEFAULT_DEVICE_ADDRESS, SingleTact};
+
     let i2c = I2cdev::new("/dev/i2c-1").unwrap();
     let mut sensor = SingleTact::new(i2c, DEFAULT_DEVICE_ADDRESS);
     let info = sensor.get_info().unwrap();
     println!("{:?}", info);
 }
+
+#[cfg(feature = "async")]
+fn main() {
+    eprintln!("This example uses the blocking API, build it without the `async` feature");
+}
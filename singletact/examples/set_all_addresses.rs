@@ -1,73 +1,53 @@
-use embedded_hal::delay::DelayNs;
-// use embedded_hal_bus::i2c as i2c_bus;
-// use embedded_hal_bus::util::AtomicCell;
-use linux_embedded_hal::{Delay, I2cdev};
-// use pca9548::prelude::*;
-use singletact::{DEFAULT_DEVICE_ADDRESS, SingleTact};
-// use sparkfun_qwiic_gpio::{ALL_OUTPUTS, PinLevel, SparkfunQwiicGpio};
-// use std::cell::RefCell;
-
-const MUX_BASE: u8 = 30;
-
+#[cfg(not(feature = "async"))]
 fn main() {
-    // let i2c = I2cdev::new("/dev/i2c-1").unwrap();
-    // let shared_i2c = RefCell::new(i2c);
-    // let i2c_cell = AtomicCell::new(i2c);
-    let mut delay = Delay;
-
-    // let mut gpio = SparkfunQwiicGpio::new(&i2c_bus::RefCellDevice::new(&shared_i2c));
-    // let mut gpio = SparkfunQwiicGpio::new(i2c_bus::AtomicDevice::new(&i2c_cell));
-    // let mut mux = PCA9548::new(i2c_bus::AtomicDevice::new(&i2c_cell))
-    //     .with_ports_disabled()
-    //     .unwrap();
-    // delay.delay_ms(100u32);
-    // gpio.init().unwrap();
-    // gpio.set_port_config(ALL_OUTPUTS).unwrap();
+    use pca9548::linux::SysfsMux;
+    use singletact::provision::{LinuxBuses, Outcome, Provisioner, Sequential};
 
-    for i in 0..8 {
-        let bus = MUX_BASE + i;
-        let i2c = I2cdev::new(format!("/dev/i2c-{}", bus)).unwrap();
+    // Adapter and address of the multiplexer driven by `i2c-mux-pca954x`
+    const MUX_BUS: u32 = 1;
+    const MUX_ADDRESS: u8 = 0x70;
+    // Address of the sensor on the first channel, the others follow
+    const FIRST_ADDRESS: u8 = 0x10;
 
-        let id = 0x10 + i;
-        println!(
-            "Assigning sensor in position {} on bus {} address 0x{:02x}",
-            i, bus, id
-        );
-        // gpio.set_pin_output(i, PinLevel::High).unwrap();
-        // mux.set_port(i, true).unwrap();
-        // delay.delay_ms(500u32);
+    let sysfs_mux = SysfsMux::find(MUX_BUS, MUX_ADDRESS).unwrap();
+    let paths: Vec<_> = sysfs_mux
+        .channels()
+        .filter_map(|(channel, _)| sysfs_mux.device_path(channel))
+        .collect();
 
-        // let mut sensor = SingleTact::new(
-        //     // &i2c_bus::RefCellDevice::new(&shared_i2c),
-        //     i2c_bus::AtomicDevice::new(&i2c_cell),
-        //     DEFAULT_DEVICE_ADDRESS,
-        // );
-        let mut sensor = SingleTact::new(i2c, DEFAULT_DEVICE_ADDRESS);
-        let initial_info = sensor.get_info();
-        if let Err(e) = initial_info {
-            eprintln!("No response from sensor at default address: {:?}", e);
-            continue;
+    let mut provisioner = Provisioner::new(LinuxBuses::new(&paths), Sequential(FIRST_ADDRESS));
+    let report = match provisioner.run::<8>() {
+        Ok(report) => report,
+        Err((report, e)) => {
+            eprintln!("Provisioning aborted: {:?}", e);
+            report
         }
-        // println!("{:?}", initial_info.unwrap());
-        if let Err(e) = sensor.set_address(id) {
-            eprintln!("No response from sensor at default address: {:?}", e);
-            continue;
-        }
-        let final_info = sensor.get_info();
-        match final_info {
-            Ok(info) => {
-                let addr = initial_info.unwrap().address;
-                if addr != info.address {
-                    println!("Address changed from {} to {}", addr, info.address);
-                } else {
-                    println!("Address changed to {} successfully.", info.address);
-                }
+    };
+
+    for (position, outcome) in report.outcomes().iter().enumerate() {
+        match outcome {
+            Outcome::Assigned(assignment) => println!(
+                "Sensor {:#06x} in position {} assigned address 0x{:02x}",
+                assignment.serial, position, assignment.address
+            ),
+            Outcome::AlreadyAssigned(assignment) => println!(
+                "Sensor {:#06x} in position {} already at address 0x{:02x}",
+                assignment.serial, position, assignment.address
+            ),
+            Outcome::Missing => eprintln!("No response from sensor in position {}", position),
+            Outcome::Skipped => {}
+            Outcome::DuplicateAddress => eprintln!(
+                "Address of sensor in position {} already taken by an earlier one",
+                position
+            ),
+            Outcome::Failed(e) => {
+                eprintln!("Failed to assign sensor in position {}: {:?}", position, e)
             }
-            Err(e) => eprintln!("Failed to get info from sensor at new address: {:?}", e),
         }
-
-        // gpio.set_pin_output(i, PinLevel::Low).unwrap();
-        // mux.set_port(i, false).unwrap();
-        delay.delay_ms(1000u32);
     }
 }
+
+#[cfg(feature = "async")]
+fn main() {
+    eprintln!("Provisioning is only available in blocking mode, build without the `async` feature");
+}
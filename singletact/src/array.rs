@@ -0,0 +1,175 @@
+//! Several sensors read in one sweep.
+//!
+//! Each sensor gets its own bus handle, e.g. a shared bus device from
+//! `embedded-hal-bus` for sensors at different addresses, or a multiplexer
+//! port for sensors behind a PCA954x. A sensor failing does not stop the
+//! sweep, its error is returned in its slot of the [`ArrayFrame`] and
+//! counted in its [`SensorHealth`].
+
+use crate::{Error, SensorFrameMeasurement, SingleTact};
+use embedded_hal::i2c::ErrorType;
+#[cfg(not(feature = "async"))]
+use embedded_hal::i2c::I2c;
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+/// Host time source stamping each sweep.
+pub trait HostClock {
+    /// Current time in a unit of the host's choosing, e.g. microseconds.
+    fn now(&self) -> u64;
+}
+
+impl<F> HostClock for F
+where
+    F: Fn() -> u64,
+{
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// Clock that never advances, every sweep is stamped 0, for arrays whose
+/// sweeps need no timestamp.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoClock;
+
+impl HostClock for NoClock {
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+/// Reliability of one sensor of the array.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SensorHealth {
+    /// Failed reads since the last good one.
+    pub consecutive_errors: u32,
+    /// Failed reads since the array was created.
+    pub errors: u32,
+    /// Last frame read successfully.
+    pub last_good: Option<SensorFrameMeasurement>,
+    /// Host timestamp of the sweep that read [`SensorHealth::last_good`].
+    pub last_good_timestamp: Option<u64>,
+}
+
+impl SensorHealth {
+    /// Returns `true` if the last read succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.last_good.is_some() && self.consecutive_errors == 0
+    }
+
+    fn record<E>(&mut self, timestamp: u64, reading: &Result<SensorFrameMeasurement, Error<E>>) {
+        match reading {
+            Ok(frame) => {
+                self.consecutive_errors = 0;
+                self.last_good = Some(*frame);
+                self.last_good_timestamp = Some(timestamp);
+            }
+            Err(_) => {
+                self.consecutive_errors = self.consecutive_errors.saturating_add(1);
+                self.errors = self.errors.saturating_add(1);
+            }
+        }
+    }
+}
+
+/// Readings of every sensor from one sweep.
+#[derive(Debug)]
+pub struct ArrayFrame<E, const N: usize> {
+    /// Host time at the start of the sweep.
+    pub timestamp: u64,
+    /// Reading of each sensor, in the order the sensors were given.
+    pub readings: [Result<SensorFrameMeasurement, Error<E>>; N],
+}
+
+impl<E, const N: usize> ArrayFrame<E, N> {
+    /// Positions and frames of the sensors read successfully.
+    pub fn frames(&self) -> impl Iterator<Item = (usize, SensorFrameMeasurement)> + '_ {
+        self.readings
+            .iter()
+            .enumerate()
+            .filter_map(|(position, reading)| Some((position, *reading.as_ref().ok()?)))
+    }
+
+    /// Returns `true` if every sensor was read successfully.
+    pub fn is_complete(&self) -> bool {
+        self.readings.iter().all(Result::is_ok)
+    }
+}
+
+/// `N` sensors read together.
+#[derive(Debug)]
+pub struct SensorArray<I2C, const N: usize, C> {
+    sensors: [SingleTact<I2C>; N],
+    health: [SensorHealth; N],
+    clock: C,
+}
+
+impl<I2C, const N: usize, C> SensorArray<I2C, N, C>
+where
+    C: HostClock,
+{
+    /// Read `sensors` together, each on its own bus handle, and stamp sweeps
+    /// with `clock`.
+    pub fn new(sensors: [SingleTact<I2C>; N], clock: C) -> Self {
+        SensorArray {
+            sensors,
+            health: [SensorHealth::default(); N],
+            clock,
+        }
+    }
+}
+
+impl<I2C, const N: usize, C> SensorArray<I2C, N, C> {
+    /// Health of each sensor.
+    pub fn health(&self) -> &[SensorHealth; N] {
+        &self.health
+    }
+
+    /// Access the sensors between sweeps.
+    pub fn sensors_mut(&mut self) -> &mut [SingleTact<I2C>; N] {
+        &mut self.sensors
+    }
+
+    /// Destroy the array, return the sensors.
+    pub fn destroy(self) -> [SingleTact<I2C>; N] {
+        self.sensors
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "SensorArray",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C, const N: usize, C> SensorArray<I2C, N, C>
+where
+    I2C: AsyncI2c + ErrorType,
+    C: HostClock,
+{
+    /// Read the frame of every sensor, one after the other.
+    pub async fn sweep(&mut self) -> ArrayFrame<I2C::Error, N> {
+        let timestamp = self.clock.now();
+        let mut readings = [const { None }; N];
+        for ((sensor, health), reading) in self
+            .sensors
+            .iter_mut()
+            .zip(self.health.iter_mut())
+            .zip(readings.iter_mut())
+        {
+            let result = sensor.read_sensor_frame().await;
+            health.record(timestamp, &result);
+            *reading = Some(result);
+        }
+
+        // `sensors` and `readings` both hold `N` items, so the loop above
+        // filled every slot and no `None` can reach the frame
+        ArrayFrame {
+            timestamp,
+            readings: readings.map(|reading| reading.expect("every sensor was read")),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
-    END_OF_PACKET, Error, READ_COMMAND, Register, SensorFrameMeasurement, SensorInfo, SingleTact,
-    WRITE_COMMAND,
+    END_OF_PACKET, Error, ForceReading, MAX_COUNT, MAX_WRITE_LENGTH, READ_COMMAND, Register,
+    SensorConfig, SensorFrameMeasurement, SensorInfo, SensorModel, SingleTact, Tare,
+    VALID_ADDRESSES, WRITE_COMMAND,
 };
 use embedded_hal::i2c::ErrorType;
 #[cfg(not(feature = "async"))]
@@ -21,10 +22,29 @@ where
     I2C: AsyncI2c + ErrorType,
 {
     /// Set the device address.
+    ///
+    /// The address must be a non-reserved 7 bit address. The change is
+    /// confirmed by reading the sensor information at the new address. If the
+    /// sensor does not answer there, the driver goes back to the old address
+    /// and fails with [`Error::AddressNotChanged`] if the sensor is found there,
+    /// or [`Error::SensorLost`] if it is not.
     pub async fn set_address(&mut self, address: u8) -> Result<(), Error<I2C::Error>> {
+        if !VALID_ADDRESSES.contains(&address) {
+            return Err(Error::InvalidInputData);
+        }
         self.write_register(Register::ADDRESS, address).await?;
-        self.address = address;
-        Ok(())
+
+        let previous = core::mem::replace(&mut self.address, address);
+        match self.get_info().await {
+            Ok(info) if info.address == address => Ok(()),
+            _ => {
+                self.address = previous;
+                match self.get_info().await {
+                    Ok(_) => Err(Error::AddressNotChanged),
+                    Err(_) => Err(Error::SensorLost),
+                }
+            }
+        }
     }
 
     /// Get sensor information.
@@ -44,6 +64,53 @@ where
         })
     }
 
+    /// Read the sensor configuration.
+    pub async fn read_config(&mut self) -> Result<SensorConfig, Error<I2C::Error>> {
+        // The firmware revision sits between the gain and discharge time registers
+        let mut data = [0; 7];
+        self.read_registers(Register::ACCUMULATOR, &mut data)
+            .await?;
+        Ok(SensorConfig {
+            accumulator: data[0],
+            gain: data[1],
+            discharge_time: data[3],
+            output_current: data[4],
+            output_scale: u16::from(data[5]) << 8 | u16::from(data[6]),
+        })
+    }
+
+    /// Write the sensor configuration.
+    ///
+    /// Nothing is written if a field is out of range.
+    pub async fn write_config(&mut self, config: &SensorConfig) -> Result<(), Error<I2C::Error>> {
+        if !config.is_valid() {
+            return Err(Error::InvalidInputData);
+        }
+        self.write_registers(Register::ACCUMULATOR, &[config.accumulator, config.gain])
+            .await?;
+        let [scale_high, scale_low] = config.output_scale.to_be_bytes();
+        self.write_registers(
+            Register::DISCHARGE_TIME,
+            &[
+                config.discharge_time,
+                config.output_current,
+                scale_high,
+                scale_low,
+            ],
+        )
+        .await
+    }
+
+    /// Get the number of sensing elements.
+    pub async fn get_num_elements(&mut self) -> Result<u8, Error<I2C::Error>> {
+        self.read_register(Register::NUM_ELEMENTS).await
+    }
+
+    /// Returns `true` if the sensor has been factory calibrated.
+    pub async fn is_calibrated(&mut self) -> Result<bool, Error<I2C::Error>> {
+        Ok(self.read_register(Register::CALIBRATED).await? != 0)
+    }
+
     /// Read the measurement data of all channels at once.
     pub async fn read_sensor_frame(&mut self) -> Result<SensorFrameMeasurement, Error<I2C::Error>> {
         let mut data = [0; 6];
@@ -56,11 +123,81 @@ where
         })
     }
 
+    /// Average `samples` frames taken with no load and report subsequent
+    /// readings relative to them.
+    ///
+    /// Fails with [`Error::InvalidInputData`] if the average is above
+    /// [`MAX_COUNT`], which a working sensor does not report.
+    pub async fn tare(&mut self, samples: u16) -> Result<Tare, Error<I2C::Error>> {
+        if samples == 0 {
+            return Err(Error::InvalidInputData);
+        }
+        let mut sum = 0u32;
+        for _ in 0..samples {
+            sum += u32::from(self.read_sensor_frame().await?.output);
+        }
+        let average = (sum + u32::from(samples) / 2) / u32::from(samples);
+        let tare = Tare::new(average as u16).ok_or(Error::InvalidInputData)?;
+        self.tare = Some(tare);
+        Ok(tare)
+    }
+
+    /// Read the output relative to the tare, or to the nominal baseline if
+    /// the sensor has not been tared.
+    pub async fn read_tared_output(&mut self) -> Result<i16, Error<I2C::Error>> {
+        let frame = self.read_sensor_frame().await?;
+        Ok(self.baseline().apply(frame.output))
+    }
+
+    /// Read the force relative to the tare, or to the nominal baseline if the
+    /// sensor has not been tared.
+    pub async fn read_force(
+        &mut self,
+        model: SensorModel,
+    ) -> Result<ForceReading, Error<I2C::Error>> {
+        let frame = self.read_sensor_frame().await?;
+        Ok(frame.force(model, self.baseline().baseline()))
+    }
+
+    /// Write the baseline register.
+    ///
+    /// Only firmwares that support re-zeroing on the sensor accept the write.
+    pub async fn write_baseline(&mut self, baseline: u16) -> Result<(), Error<I2C::Error>> {
+        if baseline > MAX_COUNT {
+            return Err(Error::InvalidInputData);
+        }
+        self.write_registers(Register::BASELINE, &baseline.to_be_bytes())
+            .await
+    }
+
+    fn baseline(&self) -> Tare {
+        self.tare.unwrap_or(Tare::NOMINAL)
+    }
+
     /// Write to a register.
     async fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error<I2C::Error>> {
-        let mut buffer = [WRITE_COMMAND, register, 0x01, value, END_OF_PACKET];
+        let buffer = [WRITE_COMMAND, register, 0x01, value, END_OF_PACKET];
+        self.i2c
+            .write(self.address, &buffer)
+            .await
+            .map_err(Error::I2C)
+    }
+
+    /// Write consecutive registers.
+    async fn write_registers(
+        &mut self,
+        register: u8,
+        data: &[u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        if data.len() > MAX_WRITE_LENGTH {
+            return Err(Error::InvalidInputData);
+        }
+        let mut buffer = [0; MAX_WRITE_LENGTH + 4];
+        buffer[..3].copy_from_slice(&[WRITE_COMMAND, register, data.len() as u8]);
+        buffer[3..3 + data.len()].copy_from_slice(data);
+        buffer[3 + data.len()] = END_OF_PACKET;
         self.i2c
-            .write(self.address, &mut buffer)
+            .write(self.address, &buffer[..data.len() + 4])
             .await
             .map_err(Error::I2C)
     }
@@ -0,0 +1,372 @@
+//! Contact events detected from successive readings.
+//!
+//! [`EventDetector`] turns readings into contact and load events. Each level
+//! is a [`Band`] with separate rising and falling thresholds so noise around
+//! a threshold does not toggle it, and a crossing has to hold for the dwell
+//! time, measured with the sensor timestamp, before it is reported.
+//! [`EventStream`] reads a sensor until the next event.
+
+use crate::force::{FULL_SCALE_COUNTS, Force, NOMINAL_BASELINE, SensorModel};
+use crate::{Error, Sample, Sampler, SingleTact};
+use embedded_hal::i2c::ErrorType;
+#[cfg(not(feature = "async"))]
+use embedded_hal::i2c::I2c;
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+/// What happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// The load rose through the contact band.
+    ContactMade,
+    /// The load fell through the contact band.
+    ContactReleased,
+    /// The load rose through the threshold band.
+    ThresholdExceeded,
+    /// The load fell back through the threshold band.
+    ThresholdCleared,
+    /// Contact was held for the sustain time.
+    SustainedLoad,
+}
+
+/// Event with the sensor timestamp (0.1 ms increments, see
+/// [`Sample::timestamp`]) it took effect.
+///
+/// Crossings are stamped with the reading that first crossed the band, the
+/// start of the dwell time, sustained load with the reading that reached the
+/// sustain time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Event {
+    /// What happened.
+    pub kind: EventKind,
+    /// When it happened.
+    pub timestamp: u64,
+}
+
+/// Thresholds with hysteresis, in counts above the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    rising: f32,
+    falling: f32,
+}
+
+impl Band {
+    /// Band entered at `rising` counts above the baseline and left below
+    /// `falling`, `None` if `falling` is above `rising`.
+    pub fn counts(rising: u16, falling: u16) -> Option<Self> {
+        if falling > rising {
+            return None;
+        }
+        Some(Band {
+            rising: f32::from(rising),
+            falling: f32::from(falling),
+        })
+    }
+
+    /// Band entered at a force of `rising` and left below `falling` on a
+    /// sensor of the given range, `None` if `falling` is above `rising`.
+    pub fn force(model: SensorModel, rising: Force, falling: Force) -> Option<Self> {
+        if falling.newtons() > rising.newtons() {
+            return None;
+        }
+        let counts = |force: Force| {
+            force.newtons() * f32::from(FULL_SCALE_COUNTS) / model.full_scale().newtons()
+        };
+        Some(Band {
+            rising: counts(rising),
+            falling: counts(falling),
+        })
+    }
+
+    /// Level the band is entered at, in counts above the baseline.
+    pub fn rising(&self) -> f32 {
+        self.rising
+    }
+
+    /// Level the band is left below, in counts above the baseline.
+    pub fn falling(&self) -> f32 {
+        self.falling
+    }
+}
+
+/// Band state that changes only after a crossing held for the dwell time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Debounce {
+    band: Band,
+    active: bool,
+    crossed: Option<u64>,
+}
+
+impl Debounce {
+    const fn new(band: Band) -> Self {
+        Debounce {
+            band,
+            active: false,
+            crossed: None,
+        }
+    }
+
+    /// Returns the time of the crossing once the state changed.
+    fn update(&mut self, timestamp: u64, load: f32, dwell: u64) -> Option<u64> {
+        let crossing = if self.active {
+            load < self.band.falling
+        } else {
+            load >= self.band.rising
+        };
+        if !crossing {
+            self.crossed = None;
+            return None;
+        }
+
+        let crossed = *self.crossed.get_or_insert(timestamp);
+        if timestamp.saturating_sub(crossed) < dwell {
+            return None;
+        }
+        self.active = !self.active;
+        self.crossed = None;
+        Some(crossed)
+    }
+
+    fn reset(&mut self) {
+        self.active = false;
+        self.crossed = None;
+    }
+}
+
+/// Events of a single reading, in the order they happened.
+///
+/// A reading crosses the threshold band at most once, either way, crosses the
+/// contact band at most once and reaches the sustain time at most once, so
+/// three events is the most it yields.
+#[derive(Debug, Clone, Default)]
+pub struct DetectedEvents {
+    events: [Option<Event>; 3],
+    next: usize,
+}
+
+impl DetectedEvents {
+    fn push(&mut self, kind: EventKind, timestamp: u64) {
+        let slot = self.events.iter_mut().find(|slot| slot.is_none());
+        debug_assert!(slot.is_some(), "a reading yields at most three events");
+        if let Some(slot) = slot {
+            *slot = Some(Event { kind, timestamp });
+        }
+    }
+}
+
+impl Iterator for DetectedEvents {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.events.get_mut(self.next)?.take();
+        self.next += 1;
+        event
+    }
+}
+
+/// Detects contact and load events in successive readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventDetector {
+    contact: Debounce,
+    threshold: Option<Debounce>,
+    dwell: u64,
+    sustain: Option<u64>,
+    baseline: u16,
+    contact_since: Option<u64>,
+    sustained: bool,
+}
+
+impl EventDetector {
+    /// Detect contact with the load in the `contact` band.
+    pub const fn new(contact: Band) -> Self {
+        EventDetector {
+            contact: Debounce::new(contact),
+            threshold: None,
+            dwell: 0,
+            sustain: None,
+            baseline: NOMINAL_BASELINE,
+            contact_since: None,
+            sustained: false,
+        }
+    }
+
+    /// Also report the load crossing the `threshold` band.
+    pub const fn with_threshold(mut self, threshold: Band) -> Self {
+        self.threshold = Some(Debounce::new(threshold));
+        self
+    }
+
+    /// Report crossings only once they held for `dwell` timestamp increments.
+    pub const fn with_dwell(mut self, dwell: u64) -> Self {
+        self.dwell = dwell;
+        self
+    }
+
+    /// Report contact held for `sustain` timestamp increments.
+    pub const fn with_sustain(mut self, sustain: u64) -> Self {
+        self.sustain = Some(sustain);
+        self
+    }
+
+    /// Measure load from `baseline`, [`NOMINAL_BASELINE`] by default, e.g.
+    /// the baseline of a [`Tare`](crate::Tare).
+    pub const fn with_baseline(mut self, baseline: u16) -> Self {
+        self.baseline = baseline;
+        self
+    }
+
+    /// Returns `true` while in contact.
+    pub fn in_contact(&self) -> bool {
+        self.contact.active
+    }
+
+    /// Returns `true` while above the threshold band.
+    pub fn above_threshold(&self) -> bool {
+        self.threshold.is_some_and(|threshold| threshold.active)
+    }
+
+    /// Detect events in a raw output, possibly filtered, read at `timestamp`.
+    pub fn update(&mut self, timestamp: u64, output: f32) -> DetectedEvents {
+        let mut events = DetectedEvents::default();
+        let load = output - f32::from(self.baseline);
+
+        // Leaving the threshold band comes before releasing contact,
+        // entering it after making contact
+        let threshold = self.threshold.as_mut().and_then(|threshold| {
+            Some((
+                threshold.update(timestamp, load, self.dwell)?,
+                threshold.active,
+            ))
+        });
+        if let Some((crossed, false)) = threshold {
+            events.push(EventKind::ThresholdCleared, crossed);
+        }
+
+        if let Some(crossed) = self.contact.update(timestamp, load, self.dwell) {
+            if self.contact.active {
+                events.push(EventKind::ContactMade, crossed);
+                self.contact_since = Some(crossed);
+                self.sustained = false;
+            } else {
+                events.push(EventKind::ContactReleased, crossed);
+                self.contact_since = None;
+            }
+        }
+
+        if let Some((crossed, true)) = threshold {
+            events.push(EventKind::ThresholdExceeded, crossed);
+        }
+
+        if let (Some(since), Some(sustain)) = (self.contact_since, self.sustain)
+            && !self.sustained
+            && timestamp.saturating_sub(since) >= sustain
+        {
+            self.sustained = true;
+            events.push(EventKind::SustainedLoad, timestamp);
+        }
+        events
+    }
+
+    /// Detect events in the output of a sample, duplicate frames are skipped.
+    pub fn update_sample(&mut self, sample: &Sample) -> DetectedEvents {
+        if sample.duplicate {
+            return DetectedEvents::default();
+        }
+        self.update(sample.timestamp, f32::from(sample.output))
+    }
+
+    /// Forget the current state, e.g. after the sensor was restarted.
+    pub fn reset(&mut self) {
+        self.contact.reset();
+        if let Some(threshold) = self.threshold.as_mut() {
+            threshold.reset();
+        }
+        self.contact_since = None;
+        self.sustained = false;
+    }
+}
+
+/// Sensor read until the next event.
+#[derive(Debug)]
+pub struct EventStream<I2C> {
+    sampler: Sampler<I2C>,
+    detector: EventDetector,
+    pending: DetectedEvents,
+}
+
+impl<I2C> EventStream<I2C> {
+    /// Detect events in the readings of `sensor`.
+    pub fn new(sensor: SingleTact<I2C>, detector: EventDetector) -> Self {
+        EventStream {
+            sampler: Sampler::new(sensor),
+            detector,
+            pending: DetectedEvents::default(),
+        }
+    }
+
+    /// Detector state.
+    pub fn detector(&self) -> &EventDetector {
+        &self.detector
+    }
+
+    /// Sampler reading the sensor, e.g. for frame statistics.
+    pub fn sampler_mut(&mut self) -> &mut Sampler<I2C> {
+        &mut self.sampler
+    }
+
+    /// Stop detecting events, return the sensor.
+    pub fn release(self) -> SingleTact<I2C> {
+        self.sampler.release()
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "EventStream",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C> EventStream<I2C>
+where
+    I2C: AsyncI2c + ErrorType,
+{
+    /// Read the sensor until the next event.
+    pub async fn next_event(&mut self) -> Result<Event, Error<I2C::Error>> {
+        loop {
+            if let Some(event) = self.pending.next() {
+                return Ok(event);
+            }
+            let sample = self.sampler.sample().await?;
+            self.pending = self.detector.update_sample(&sample);
+        }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<I2C> Iterator for EventStream<I2C>
+where
+    I2C: I2c,
+{
+    type Item = Result<Event, Error<I2C::Error>>;
+
+    /// Read the sensor until the next event, the stream never ends.
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C> EventStream<I2C>
+where
+    I2C: AsyncI2c,
+{
+    /// Read events as a stream that never ends.
+    pub fn into_stream(self) -> impl futures_util::Stream<Item = Result<Event, Error<I2C::Error>>> {
+        futures_util::stream::unfold(self, |mut events| async move {
+            let event = events.next_event().await;
+            Some((event, events))
+        })
+    }
+}
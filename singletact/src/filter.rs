@@ -0,0 +1,344 @@
+//! Filters smoothing successive readings.
+//!
+//! Filters work on any reading, raw counts or force, and are fed with the
+//! frame timestamp (0.1 ms increments, see [`Sample::timestamp`]) so they
+//! behave the same when frames arrive at an uneven rate or were dropped.
+//! They keep their history in fixed size buffers and chain with
+//! [`Filter::chain`]:
+//!
+//! ```
+//! use singletact::filter::{Filter, LowPass, Median};
+//!
+//! // Reject spikes, then smooth with a 5 ms time constant
+//! let mut filter = Median::<3>::new(u64::MAX).chain(LowPass::new(50));
+//! assert_eq!(filter.update(0, 300.0), 300.0);
+//! ```
+//!
+//! A reading with the same timestamp as the previous one, e.g. a duplicate
+//! frame, is not counted twice. A timestamp going backwards restarts the
+//! filter.
+
+use crate::Sample;
+use core::f32::consts::PI;
+
+/// Timestamp increments per second.
+pub const TICKS_PER_SECOND: f32 = 10_000.0;
+
+/// Filter over successive timestamped readings.
+pub trait Filter {
+    /// Filter a reading taken at `timestamp`, return the filtered value.
+    fn update(&mut self, timestamp: u64, value: f32) -> f32;
+
+    /// Forget all previous readings.
+    fn reset(&mut self);
+
+    /// Filter the raw output of a sample.
+    fn update_sample(&mut self, sample: &Sample) -> f32 {
+        self.update(sample.timestamp, f32::from(sample.output))
+    }
+
+    /// Feed the output of this filter into `next`.
+    fn chain<F>(self, next: F) -> Chain<Self, F>
+    where
+        Self: Sized,
+        F: Filter,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+impl<T> Filter for &mut T
+where
+    T: Filter + ?Sized,
+{
+    fn update(&mut self, timestamp: u64, value: f32) -> f32 {
+        T::update(self, timestamp, value)
+    }
+
+    fn reset(&mut self) {
+        T::reset(self)
+    }
+}
+
+/// Two filters applied one after the other, see [`Filter::chain`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Chain<A, B> {
+    /// Split the chain into its filters.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A, B> Filter for Chain<A, B>
+where
+    A: Filter,
+    B: Filter,
+{
+    fn update(&mut self, timestamp: u64, value: f32) -> f32 {
+        let value = self.first.update(timestamp, value);
+        self.second.update(timestamp, value)
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+}
+
+/// The last `N` readings taken within a time window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Window<const N: usize> {
+    readings: [(u64, f32); N],
+    start: usize,
+    len: usize,
+    span: u64,
+}
+
+impl<const N: usize> Window<N> {
+    /// Evaluated for every `N` a window is created with, so an empty window
+    /// fails to build
+    const NON_EMPTY: () = assert!(N > 0, "window must hold at least one reading");
+
+    const fn new(span: u64) -> Self {
+        let () = Self::NON_EMPTY;
+        Window {
+            readings: [(0, 0.0); N],
+            start: 0,
+            len: 0,
+            span,
+        }
+    }
+
+    fn newest(&self) -> Option<(u64, f32)> {
+        (self.len > 0).then(|| self.readings[(self.start + self.len - 1) % N])
+    }
+
+    fn push(&mut self, timestamp: u64, value: f32) {
+        match self.newest() {
+            Some((newest, _)) if timestamp < newest => self.clear(),
+            Some((newest, _)) if timestamp == newest => {
+                self.readings[(self.start + self.len - 1) % N] = (timestamp, value);
+                return;
+            }
+            _ => {}
+        }
+
+        if self.len == N {
+            self.start = (self.start + 1) % N;
+            self.len -= 1;
+        }
+        self.readings[(self.start + self.len) % N] = (timestamp, value);
+        self.len += 1;
+
+        // Drop readings that fell out of the time window
+        while timestamp - self.readings[self.start].0 >= self.span && self.len > 1 {
+            self.start = (self.start + 1) % N;
+            self.len -= 1;
+        }
+    }
+
+    fn values(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.len).map(|i| self.readings[(self.start + i) % N].1)
+    }
+
+    fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+/// Mean of the last `N` readings taken within a time window.
+///
+/// Readings older than the window are left out, so the average covers the
+/// same span of time however fast the sensor is read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovingAverage<const N: usize> {
+    window: Window<N>,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    /// Average over at most `N` readings taken less than `window` timestamp
+    /// increments apart from the newest, `u64::MAX` to average the last `N`.
+    ///
+    /// `N` must not be zero:
+    ///
+    /// ```compile_fail
+    /// let average = singletact::filter::MovingAverage::<0>::new(u64::MAX);
+    /// ```
+    pub const fn new(window: u64) -> Self {
+        MovingAverage {
+            window: Window::new(window),
+        }
+    }
+}
+
+impl<const N: usize> Filter for MovingAverage<N> {
+    fn update(&mut self, timestamp: u64, value: f32) -> f32 {
+        self.window.push(timestamp, value);
+        self.window.values().sum::<f32>() / self.window.len as f32
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+/// Median of the last `N` readings taken within a time window.
+///
+/// Removes spikes shorter than half the window without smearing steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Median<const N: usize> {
+    window: Window<N>,
+}
+
+impl<const N: usize> Median<N> {
+    /// Median of at most `N` readings taken less than `window` timestamp
+    /// increments apart from the newest, `u64::MAX` for the last `N`.
+    pub const fn new(window: u64) -> Self {
+        Median {
+            window: Window::new(window),
+        }
+    }
+}
+
+impl<const N: usize> Filter for Median<N> {
+    fn update(&mut self, timestamp: u64, value: f32) -> f32 {
+        self.window.push(timestamp, value);
+
+        let mut sorted = [0.0; N];
+        let len = self.window.len;
+        for (slot, value) in sorted.iter_mut().zip(self.window.values()) {
+            *slot = value;
+        }
+        let sorted = &mut sorted[..len];
+        sorted.sort_unstable_by(f32::total_cmp);
+
+        if len % 2 == 1 {
+            sorted[len / 2]
+        } else {
+            (sorted[len / 2 - 1] + sorted[len / 2]) / 2.0
+        }
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+/// Smoothing factor of a first order low-pass with time constant `tau`
+/// after `dt`, both in the same unit.
+fn smoothing(tau: f32, dt: f32) -> f32 {
+    dt / (tau + dt)
+}
+
+/// Exponential low-pass filter.
+///
+/// The smoothing factor is derived from the time since the previous reading,
+/// a reading after a gap moves the output further than one right after the
+/// previous.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LowPass {
+    time_constant: f32,
+    last: Option<(u64, f32)>,
+}
+
+impl LowPass {
+    /// Low-pass with a time constant of `time_constant` timestamp increments.
+    pub const fn new(time_constant: u32) -> Self {
+        LowPass {
+            time_constant: time_constant as f32,
+            last: None,
+        }
+    }
+
+    /// Low-pass with the given cut-off frequency in Hz.
+    pub fn from_cutoff(cutoff: f32) -> Self {
+        LowPass {
+            time_constant: TICKS_PER_SECOND / (2.0 * PI * cutoff),
+            last: None,
+        }
+    }
+}
+
+impl Filter for LowPass {
+    fn update(&mut self, timestamp: u64, value: f32) -> f32 {
+        let output = match self.last {
+            Some((last, output)) if timestamp > last => {
+                let dt = (timestamp - last) as f32;
+                output + smoothing(self.time_constant, dt) * (value - output)
+            }
+            Some((last, output)) if timestamp == last => output,
+            _ => value,
+        };
+        self.last = Some((timestamp, output));
+        output
+    }
+
+    fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// The 1€ filter, an adaptive low-pass.
+///
+/// Smooths heavily while the reading is steady and lowers the lag as it
+/// changes faster, see <https://gery.casiez.net/1euro/>.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OneEuro {
+    min_cutoff: f32,
+    beta: f32,
+    derivative_cutoff: f32,
+    /// Timestamp, filtered value and filtered rate of change per second
+    last: Option<(u64, f32, f32)>,
+}
+
+impl OneEuro {
+    /// 1€ filter with a cut-off of `min_cutoff` Hz at rest, rising by `beta`
+    /// Hz per unit per second of change.
+    pub const fn new(min_cutoff: f32, beta: f32) -> Self {
+        OneEuro {
+            min_cutoff,
+            beta,
+            derivative_cutoff: 1.0,
+            last: None,
+        }
+    }
+
+    /// Cut-off in Hz of the rate of change estimate, 1 Hz by default.
+    pub const fn with_derivative_cutoff(mut self, cutoff: f32) -> Self {
+        self.derivative_cutoff = cutoff;
+        self
+    }
+}
+
+impl Filter for OneEuro {
+    fn update(&mut self, timestamp: u64, value: f32) -> f32 {
+        let (output, rate) = match self.last {
+            Some((last, output, rate)) if timestamp > last => {
+                let dt = (timestamp - last) as f32 / TICKS_PER_SECOND;
+                let tau = |cutoff: f32| 1.0 / (2.0 * PI * cutoff);
+
+                let raw_rate = (value - output) / dt;
+                let rate = rate + smoothing(tau(self.derivative_cutoff), dt) * (raw_rate - rate);
+                let cutoff = self.min_cutoff + self.beta * rate.abs();
+                (output + smoothing(tau(cutoff), dt) * (value - output), rate)
+            }
+            Some((last, output, rate)) if timestamp == last => (output, rate),
+            _ => (value, 0.0),
+        };
+        self.last = Some((timestamp, output, rate));
+        output
+    }
+
+    fn reset(&mut self) {
+        self.last = None;
+    }
+}
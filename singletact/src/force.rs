@@ -0,0 +1,98 @@
+//! Conversion of raw sensor counts to force.
+
+use crate::SensorFrameMeasurement;
+
+/// Raw count the sensor reports at no load, before calibration.
+pub const NOMINAL_BASELINE: u16 = 255;
+/// Counts between the baseline and full-scale load.
+pub const FULL_SCALE_COUNTS: u16 = 511;
+/// Largest count of the 10 bit output.
+pub const MAX_COUNT: u16 = 1023;
+
+const STANDARD_GRAVITY: f32 = 9.806_65;
+
+/// Force range of a sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SensorModel {
+    /// 1 N full scale.
+    Range1N,
+    /// 4.5 N full scale.
+    Range4N5,
+    /// 10 N full scale.
+    Range10N,
+    /// 45 N full scale.
+    Range45N,
+    /// 100 N full scale.
+    Range100N,
+    /// 450 N full scale.
+    Range450N,
+}
+
+impl SensorModel {
+    /// Force at the top of the range.
+    pub fn full_scale(self) -> Force {
+        Force::from_newtons(match self {
+            SensorModel::Range1N => 1.0,
+            SensorModel::Range4N5 => 4.5,
+            SensorModel::Range10N => 10.0,
+            SensorModel::Range45N => 45.0,
+            SensorModel::Range100N => 100.0,
+            SensorModel::Range450N => 450.0,
+        })
+    }
+
+    /// Convert a raw count to force, relative to `baseline`.
+    ///
+    /// `baseline` is the no-load count, e.g. [`SensorInfo::baseline`](crate::SensorInfo::baseline)
+    /// or [`NOMINAL_BASELINE`]. Counts below the baseline give a negative force.
+    pub fn force(self, output: u16, baseline: u16) -> ForceReading {
+        let counts = f32::from(output) - f32::from(baseline);
+        let newtons = counts * self.full_scale().newtons() / f32::from(FULL_SCALE_COUNTS);
+        ForceReading {
+            force: Force::from_newtons(newtons),
+            saturated: output >= MAX_COUNT || output >= baseline.saturating_add(FULL_SCALE_COUNTS),
+        }
+    }
+}
+
+/// Force in newtons.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Force(f32);
+
+impl Force {
+    /// Create a force from newtons.
+    pub const fn from_newtons(newtons: f32) -> Self {
+        Force(newtons)
+    }
+
+    /// Create a force from grams-force.
+    pub fn from_grams_force(grams: f32) -> Self {
+        Force(grams * STANDARD_GRAVITY / 1000.0)
+    }
+
+    /// Force in newtons.
+    pub const fn newtons(self) -> f32 {
+        self.0
+    }
+
+    /// Force in grams-force.
+    pub fn grams_force(self) -> f32 {
+        self.0 * 1000.0 / STANDARD_GRAVITY
+    }
+}
+
+/// Force computed from a raw count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForceReading {
+    /// Force relative to the baseline.
+    pub force: Force,
+    /// The count reached the top of the range, the actual force may be higher.
+    pub saturated: bool,
+}
+
+impl SensorFrameMeasurement {
+    /// Convert the output of this frame to force, see [`SensorModel::force`].
+    pub fn force(&self, model: SensorModel, baseline: u16) -> ForceReading {
+        model.force(self.output, baseline)
+    }
+}
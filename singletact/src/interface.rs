@@ -1,9 +1,15 @@
+use core::ops::RangeInclusive;
+
 /// Default I2C address for the device.
 pub const DEFAULT_DEVICE_ADDRESS: u8 = 0x04;
+/// Addresses the device can be moved to, the non-reserved 7 bit addresses.
+pub const VALID_ADDRESSES: RangeInclusive<u8> = 0x08..=0x77;
 
 pub(crate) const READ_COMMAND: u8 = 0x01;
 pub(crate) const WRITE_COMMAND: u8 = 0x02;
 pub(crate) const END_OF_PACKET: u8 = 0xFF;
+/// Largest register block written in one packet
+pub(crate) const MAX_WRITE_LENGTH: usize = 4;
 
 pub(crate) struct Register;
 
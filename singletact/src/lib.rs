@@ -10,12 +10,27 @@
 #![deny(unsafe_code, missing_docs)]
 #![no_std]
 
+#[cfg(feature = "linux")]
+extern crate std;
+
+mod array;
+pub use crate::array::{ArrayFrame, HostClock, NoClock, SensorArray, SensorHealth};
 mod device;
+pub mod event;
+pub mod filter;
+mod force;
+pub use crate::force::{
+    FULL_SCALE_COUNTS, Force, ForceReading, MAX_COUNT, NOMINAL_BASELINE, SensorModel,
+};
 mod interface;
-pub use crate::interface::DEFAULT_DEVICE_ADDRESS;
-use crate::interface::{END_OF_PACKET, READ_COMMAND, Register, WRITE_COMMAND};
+pub use crate::interface::{DEFAULT_DEVICE_ADDRESS, VALID_ADDRESSES};
+use crate::interface::{END_OF_PACKET, MAX_WRITE_LENGTH, READ_COMMAND, Register, WRITE_COMMAND};
+#[cfg(not(feature = "async"))]
+pub mod provision;
+mod sampler;
+pub use crate::sampler::{FrameTracker, Sample, Sampler};
 mod types;
-pub use crate::types::{Error, SensorFrameMeasurement, SensorInfo};
+pub use crate::types::{Error, SensorConfig, SensorFrameMeasurement, SensorInfo, Tare};
 
 /// SingleTact device driver.
 #[derive(Debug)]
@@ -24,12 +39,34 @@ pub struct SingleTact<I2C> {
     i2c: I2C,
     /// Address of the device on the bus.
     address: u8,
+    /// No-load output readings are reported relative to.
+    tare: Option<Tare>,
 }
 
 impl<I2C> SingleTact<I2C> {
     /// Create new instance of the SingleTact device.
     pub fn new(i2c: I2C, address: u8) -> Self {
-        SingleTact { i2c, address }
+        SingleTact {
+            i2c,
+            address,
+            tare: None,
+        }
+    }
+
+    /// Report readings relative to a tare measured earlier.
+    pub fn with_tare(mut self, tare: Tare) -> Self {
+        self.tare = Some(tare);
+        self
+    }
+
+    /// Replace the tare, `None` reports readings relative to the nominal baseline.
+    pub fn set_tare(&mut self, tare: Option<Tare>) {
+        self.tare = tare;
+    }
+
+    /// Current tare.
+    pub fn get_tare(&self) -> Option<Tare> {
+        self.tare
     }
 
     /// Destroy driver instance, return I²C bus instance.
@@ -0,0 +1,405 @@
+//! Assigning unique addresses to a fleet of sensors.
+//!
+//! Every sensor leaves the factory at [`DEFAULT_DEVICE_ADDRESS`], so sensors
+//! sharing a bus have to be given their own address one at a time while the
+//! others are disconnected. An [`Isolate`] strategy connects a single sensor,
+//! an [`AddressPlan`] picks its address and the [`Provisioner`] moves it
+//! there, verifies the change and records the serial number in a
+//! [`ProvisionReport`].
+//!
+//! Provisioning is a one-off setup step and is only available in blocking
+//! mode.
+
+use crate::{DEFAULT_DEVICE_ADDRESS, Error, SingleTact};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::{self, I2c};
+
+/// Connects one sensor at a time to the bus.
+pub trait Isolate {
+    /// Bus the isolated sensor is reached on.
+    type Bus<'a>: I2c<Error = Self::BusError>
+    where
+        Self: 'a;
+    /// Errors talking to the sensor.
+    type BusError: i2c::Error;
+    /// Errors connecting or disconnecting a sensor.
+    type Error;
+
+    /// Number of sensor positions.
+    fn positions(&self) -> usize;
+
+    /// Connect the sensor at `position` and disconnect every other one.
+    fn isolate(&mut self, position: usize) -> Result<Self::Bus<'_>, Self::Error>;
+
+    /// Disconnect the sensor at `position` again.
+    fn release(&mut self, position: usize) -> Result<(), Self::Error>;
+}
+
+/// Address each sensor position is moved to.
+pub trait AddressPlan {
+    /// Address for `position`, `None` to leave the sensor alone.
+    fn address(&self, position: usize) -> Option<u8>;
+}
+
+/// Consecutive addresses, the wrapped value is the address of the first sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sequential(pub u8);
+
+impl AddressPlan for Sequential {
+    fn address(&self, position: usize) -> Option<u8> {
+        u8::try_from(position).ok()?.checked_add(self.0)
+    }
+}
+
+impl AddressPlan for [u8] {
+    fn address(&self, position: usize) -> Option<u8> {
+        self.get(position).copied()
+    }
+}
+
+impl<const N: usize> AddressPlan for [u8; N] {
+    fn address(&self, position: usize) -> Option<u8> {
+        self.get(position).copied()
+    }
+}
+
+impl<T> AddressPlan for &T
+where
+    T: AddressPlan + ?Sized,
+{
+    fn address(&self, position: usize) -> Option<u8> {
+        T::address(self, position)
+    }
+}
+
+/// Sensor moved to its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Assignment {
+    /// Sensor serial number.
+    pub serial: u16,
+    /// Address assigned to the sensor.
+    pub address: u8,
+}
+
+impl Assignment {
+    /// Serialise the assignment.
+    pub const fn to_bytes(self) -> [u8; 3] {
+        let [high, low] = self.serial.to_be_bytes();
+        [high, low, self.address]
+    }
+
+    /// Deserialise an assignment stored with [`Assignment::to_bytes`].
+    pub const fn from_bytes(bytes: [u8; 3]) -> Self {
+        Assignment {
+            serial: u16::from_be_bytes([bytes[0], bytes[1]]),
+            address: bytes[2],
+        }
+    }
+}
+
+/// Result of provisioning one sensor position.
+#[derive(Debug)]
+pub enum Outcome<E> {
+    /// The sensor was moved from the default address and answers at the new one.
+    Assigned(Assignment),
+    /// The sensor already answered at its planned address.
+    AlreadyAssigned(Assignment),
+    /// No sensor answered at the default or the planned address.
+    Missing,
+    /// The position has no planned address or does not exist.
+    Skipped,
+    /// An earlier position already got the planned address, the sensor was
+    /// left alone.
+    DuplicateAddress,
+    /// Talking to the sensor failed.
+    Failed(Error<E>),
+}
+
+impl<E> Outcome<E> {
+    /// Assignment of the sensor, if it ended up at its planned address.
+    pub fn assignment(&self) -> Option<Assignment> {
+        match self {
+            Outcome::Assigned(assignment) | Outcome::AlreadyAssigned(assignment) => {
+                Some(*assignment)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of every sensor position.
+#[derive(Debug)]
+pub struct ProvisionReport<E, const N: usize> {
+    outcomes: [Outcome<E>; N],
+}
+
+impl<E, const N: usize> ProvisionReport<E, N> {
+    /// Outcomes indexed by sensor position.
+    pub fn outcomes(&self) -> &[Outcome<E>; N] {
+        &self.outcomes
+    }
+
+    /// Positions and assignments of the sensors at their planned address.
+    pub fn assignments(&self) -> impl Iterator<Item = (usize, Assignment)> + '_ {
+        self.outcomes
+            .iter()
+            .enumerate()
+            .filter_map(|(position, outcome)| Some((position, outcome.assignment()?)))
+    }
+
+    /// Returns `true` if every position that was not skipped got its address.
+    pub fn is_complete(&self) -> bool {
+        self.outcomes
+            .iter()
+            .all(|outcome| matches!(outcome, Outcome::Skipped) || outcome.assignment().is_some())
+    }
+}
+
+/// Result of [`Provisioner::run`], an error comes with the report of the
+/// positions provisioned before it.
+pub type RunResult<I, const N: usize> = Result<
+    ProvisionReport<<I as Isolate>::BusError, N>,
+    (
+        ProvisionReport<<I as Isolate>::BusError, N>,
+        <I as Isolate>::Error,
+    ),
+>;
+
+/// Assigns addresses to sensors one at a time.
+#[derive(Debug)]
+pub struct Provisioner<I, P> {
+    isolate: I,
+    plan: P,
+}
+
+impl<I, P> Provisioner<I, P>
+where
+    I: Isolate,
+    P: AddressPlan,
+{
+    /// Create a provisioner connecting sensors with `isolate` and addressing
+    /// them according to `plan`.
+    pub fn new(isolate: I, plan: P) -> Self {
+        Provisioner { isolate, plan }
+    }
+
+    /// Provision the first `N` sensor positions.
+    ///
+    /// Failures of individual sensors are recorded in the report, failing to
+    /// connect or disconnect a sensor aborts provisioning and returns the
+    /// error with the report of the positions provisioned so far.
+    pub fn run<const N: usize>(&mut self) -> RunResult<I, N> {
+        let mut report = ProvisionReport {
+            outcomes: [const { Outcome::Skipped }; N],
+        };
+        for position in 0..N.min(self.isolate.positions()) {
+            let Some(address) = self.plan.address(position) else {
+                continue;
+            };
+            if report
+                .assignments()
+                .any(|(_, assignment)| assignment.address == address)
+            {
+                report.outcomes[position] = Outcome::DuplicateAddress;
+                continue;
+            }
+
+            report.outcomes[position] = match self.isolate.isolate(position) {
+                Ok(bus) => assign(bus, address),
+                Err(error) => return Err((report, error)),
+            };
+            if let Err(error) = self.isolate.release(position) {
+                return Err((report, error));
+            }
+        }
+        Ok(report)
+    }
+
+    /// Destroy the provisioner, return the isolation strategy and address plan.
+    pub fn destroy(self) -> (I, P) {
+        (self.isolate, self.plan)
+    }
+}
+
+/// Move the only sensor on `bus` to `address`.
+fn assign<I2C>(bus: I2C, address: u8) -> Outcome<I2C::Error>
+where
+    I2C: I2c,
+{
+    let mut sensor = SingleTact::new(bus, DEFAULT_DEVICE_ADDRESS);
+    let info = match sensor.get_info() {
+        Ok(info) => info,
+        Err(_) => {
+            // Already provisioned on an earlier run
+            let mut sensor = SingleTact::new(sensor.destroy(), address);
+            return match sensor.get_info() {
+                Ok(info) if info.address == address => Outcome::AlreadyAssigned(Assignment {
+                    serial: info.serial,
+                    address,
+                }),
+                _ => Outcome::Missing,
+            };
+        }
+    };
+
+    match sensor.set_address(address) {
+        Ok(()) => Outcome::Assigned(Assignment {
+            serial: info.serial,
+            address,
+        }),
+        Err(error) => Outcome::Failed(error),
+    }
+}
+
+/// Sensors behind the channels of a PCA954x multiplexer.
+#[cfg(feature = "pca9548")]
+#[derive(Debug)]
+pub struct MuxChannels<'a, I2C, V, RST, DELAY> {
+    mux: &'a mut pca9548::PCA9548<I2C, V, RST, DELAY>,
+    channels: &'a [pca9548::Channel],
+}
+
+#[cfg(feature = "pca9548")]
+impl<'a, I2C, V, RST, DELAY> MuxChannels<'a, I2C, V, RST, DELAY> {
+    /// Sensor `i` is on `channels[i]`.
+    pub fn new(
+        mux: &'a mut pca9548::PCA9548<I2C, V, RST, DELAY>,
+        channels: &'a [pca9548::Channel],
+    ) -> Self {
+        MuxChannels { mux, channels }
+    }
+}
+
+#[cfg(feature = "pca9548")]
+impl<I2C, V, RST, DELAY> Isolate for MuxChannels<'_, I2C, V, RST, DELAY>
+where
+    I2C: I2c,
+    V: pca9548::variant::Variant,
+    RST: pca9548::reset::ResetPin<DELAY>,
+{
+    type Bus<'b>
+        = pca9548::guard::ChannelGuard<'b, I2C, V, RST, DELAY>
+    where
+        Self: 'b;
+    type BusError = pca9548::error::PCA9548Error<I2C::Error>;
+    type Error = pca9548::error::PCA9548Error<I2C::Error>;
+
+    fn positions(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn isolate(&mut self, position: usize) -> Result<Self::Bus<'_>, Self::Error> {
+        let channel = self
+            .channels
+            .get(position)
+            .ok_or(pca9548::error::PCA9548Error::PortError)?;
+        self.mux.select_exclusive(*channel)
+    }
+
+    /// The channel guard restores the previous selection when it is dropped.
+    fn release(&mut self, _position: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Sensors powered through enable lines, e.g. the pins of a port expander,
+/// sharing one bus.
+#[derive(Debug)]
+pub struct EnablePins<'a, I2C, PIN, D> {
+    i2c: &'a mut I2C,
+    pins: &'a mut [PIN],
+    delay: D,
+    power_up_ms: u32,
+}
+
+impl<'a, I2C, PIN, D> EnablePins<'a, I2C, PIN, D> {
+    /// Sensor `i` is powered while `pins[i]` is high. Sensors get
+    /// `power_up_ms` milliseconds to start before they are addressed.
+    pub fn new(i2c: &'a mut I2C, pins: &'a mut [PIN], delay: D, power_up_ms: u32) -> Self {
+        EnablePins {
+            i2c,
+            pins,
+            delay,
+            power_up_ms,
+        }
+    }
+}
+
+impl<I2C, PIN, D> Isolate for EnablePins<'_, I2C, PIN, D>
+where
+    I2C: I2c,
+    PIN: OutputPin,
+    D: DelayNs,
+{
+    type Bus<'b>
+        = &'b mut I2C
+    where
+        Self: 'b;
+    type BusError = I2C::Error;
+    type Error = PIN::Error;
+
+    fn positions(&self) -> usize {
+        self.pins.len()
+    }
+
+    fn isolate(&mut self, position: usize) -> Result<Self::Bus<'_>, Self::Error> {
+        for (index, pin) in self.pins.iter_mut().enumerate() {
+            if index != position {
+                pin.set_low()?;
+            }
+        }
+        self.pins[position].set_high()?;
+        self.delay.delay_ms(self.power_up_ms);
+        Ok(&mut *self.i2c)
+    }
+
+    fn release(&mut self, position: usize) -> Result<(), Self::Error> {
+        self.pins[position].set_low()
+    }
+}
+
+/// Sensors on their own Linux I²C adapter each, e.g. the channels of a
+/// multiplexer driven by the kernel.
+#[cfg(feature = "linux")]
+#[derive(Debug)]
+pub struct LinuxBuses<'a, P> {
+    paths: &'a [P],
+}
+
+#[cfg(feature = "linux")]
+impl<'a, P> LinuxBuses<'a, P>
+where
+    P: AsRef<std::path::Path>,
+{
+    /// Sensor `i` is on the adapter at `paths[i]`, e.g. `/dev/i2c-30`.
+    pub fn new(paths: &'a [P]) -> Self {
+        LinuxBuses { paths }
+    }
+}
+
+#[cfg(feature = "linux")]
+impl<P> Isolate for LinuxBuses<'_, P>
+where
+    P: AsRef<std::path::Path>,
+{
+    type Bus<'b>
+        = linux_embedded_hal::I2cdev
+    where
+        Self: 'b;
+    type BusError = linux_embedded_hal::I2CError;
+    type Error = linux_embedded_hal::I2CError;
+
+    fn positions(&self) -> usize {
+        self.paths.len()
+    }
+
+    fn isolate(&mut self, position: usize) -> Result<Self::Bus<'_>, Self::Error> {
+        Ok(linux_embedded_hal::I2cdev::new(&self.paths[position])?)
+    }
+
+    /// The adapter is closed when the sensor is done with it.
+    fn release(&mut self, _position: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
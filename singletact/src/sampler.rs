@@ -0,0 +1,175 @@
+//! Continuous sampling with frame tracking.
+//!
+//! The sensor numbers its frames with a 16 bit index and stamps them with a
+//! 16 bit timestamp in 0.1 ms increments, both of which wrap within seconds.
+//! [`Sampler`] extends them to 64 bit, flags frames that were read twice
+//! because the sensor was polled faster than it updates, and counts frames
+//! that were missed because it was polled too slowly.
+
+use crate::{Error, SensorFrameMeasurement, SingleTact};
+use embedded_hal::i2c::ErrorType;
+#[cfg(not(feature = "async"))]
+use embedded_hal::i2c::I2c;
+#[cfg(feature = "async")]
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+/// Frame with its index and timestamp extended to 64 bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sample {
+    /// Frame index, counting every frame since sampling started.
+    pub index: u64,
+    /// Sensor timestamp (0.1 ms increments) since sampling started.
+    pub timestamp: u64,
+    /// Sensor output (10 bit raw count).
+    pub output: u16,
+    /// The frame was already returned by the previous sample.
+    pub duplicate: bool,
+    /// Frames the sensor produced since the previous sample that were never read.
+    pub dropped: u64,
+}
+
+/// Extends frame indices and timestamps and keeps drop statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FrameTracker {
+    last: Option<SensorFrameMeasurement>,
+    index: u64,
+    timestamp: u64,
+    dropped: u64,
+    duplicates: u64,
+}
+
+impl FrameTracker {
+    /// Create a tracker that has not seen any frame.
+    pub const fn new() -> Self {
+        FrameTracker {
+            last: None,
+            index: 0,
+            timestamp: 0,
+            dropped: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// Track a frame read from the sensor.
+    pub fn update(&mut self, frame: SensorFrameMeasurement) -> Sample {
+        let (duplicate, dropped) = match self.last {
+            None => (false, 0),
+            Some(last) => {
+                let frames = u64::from(frame.index.wrapping_sub(last.index));
+                self.index += frames;
+                self.timestamp += u64::from(frame.timestamp.wrapping_sub(last.timestamp));
+                (frames == 0, frames.saturating_sub(1))
+            }
+        };
+        self.last = Some(frame);
+        self.dropped += dropped;
+        self.duplicates += u64::from(duplicate);
+
+        Sample {
+            index: self.index,
+            timestamp: self.timestamp,
+            output: frame.output,
+            duplicate,
+            dropped,
+        }
+    }
+
+    /// Frames missed since sampling started.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Frames read more than once since sampling started.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Forget the previous frame, e.g. after the sensor was restarted.
+    pub fn reset(&mut self) {
+        *self = FrameTracker::new();
+    }
+}
+
+/// Sensor read continuously.
+///
+/// The 16 bit timestamp wraps every 6.5536 s, so samples have to be taken
+/// less than that apart. Across a longer gap the timestamp, and the frame
+/// index once more than 65535 frames were missed, fall short by whole wraps.
+#[derive(Debug)]
+pub struct Sampler<I2C> {
+    sensor: SingleTact<I2C>,
+    tracker: FrameTracker,
+}
+
+impl<I2C> Sampler<I2C> {
+    /// Start sampling `sensor`.
+    pub fn new(sensor: SingleTact<I2C>) -> Self {
+        Sampler {
+            sensor,
+            tracker: FrameTracker::new(),
+        }
+    }
+
+    /// Frame statistics.
+    pub fn tracker(&self) -> &FrameTracker {
+        &self.tracker
+    }
+
+    /// Access the sensor between samples.
+    pub fn sensor_mut(&mut self) -> &mut SingleTact<I2C> {
+        &mut self.sensor
+    }
+
+    /// Stop sampling, return the sensor.
+    pub fn release(self) -> SingleTact<I2C> {
+        self.sensor
+    }
+}
+
+#[maybe_async_cfg::maybe(
+    sync(
+        cfg(not(feature = "async")),
+        self = "Sampler",
+        idents(AsyncI2c(sync = "I2c"))
+    ),
+    async(feature = "async", keep_self)
+)]
+impl<I2C> Sampler<I2C>
+where
+    I2C: AsyncI2c + ErrorType,
+{
+    /// Read the next frame.
+    pub async fn sample(&mut self) -> Result<Sample, Error<I2C::Error>> {
+        let frame = self.sensor.read_sensor_frame().await?;
+        Ok(self.tracker.update(frame))
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<I2C> Iterator for Sampler<I2C>
+where
+    I2C: I2c,
+{
+    type Item = Result<Sample, Error<I2C::Error>>;
+
+    /// Read the next frame, the sampler never runs out of frames.
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.sample())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C> Sampler<I2C>
+where
+    I2C: AsyncI2c,
+{
+    /// Read frames as a stream that never ends.
+    pub fn into_stream(
+        self,
+    ) -> impl futures_util::Stream<Item = Result<Sample, Error<I2C::Error>>> {
+        futures_util::stream::unfold(self, |mut sampler| async move {
+            let sample = sampler.sample().await;
+            Some((sample, sampler))
+        })
+    }
+}
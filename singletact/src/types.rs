@@ -1,3 +1,6 @@
+use crate::force::{MAX_COUNT, NOMINAL_BASELINE};
+use core::ops::RangeInclusive;
+
 /// All possible errors in this crate
 #[derive(Debug)]
 pub enum Error<E> {
@@ -5,6 +8,10 @@ pub enum Error<E> {
     I2C(E),
     /// Invalid input data provided.
     InvalidInputData,
+    /// The sensor did not take the new address and still answers at the old one.
+    AddressNotChanged,
+    /// The sensor answers neither at the new nor at the old address.
+    SensorLost,
 }
 
 /// Result of a measurement
@@ -20,6 +27,110 @@ pub struct SensorInfo {
     pub baseline: u16,
 }
 
+/// Writable sensor settings
+///
+/// Each field maps to one configuration register. The ranges accepted by
+/// [`SensorConfig::is_valid`] are given by the associated constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SensorConfig {
+    /// Number of conversions accumulated into one output sample.
+    ///
+    /// The range has not been checked against the SingleTact manual.
+    pub accumulator: u8,
+    /// Reference gain of the capacitance front end.
+    ///
+    /// Range unconfirmed, it is yet to be checked against the SingleTact
+    /// manual.
+    pub gain: u8,
+    /// Sensor discharge time per conversion.
+    ///
+    /// Any non-zero value is accepted, pending a check of the range against
+    /// the SingleTact manual.
+    pub discharge_time: u8,
+    /// Charge current driven into the sensor.
+    ///
+    /// The upper bound of 63 still needs checking against the SingleTact
+    /// manual.
+    pub output_current: u8,
+    /// Scaling applied to the raw count before it is reported.
+    ///
+    /// Unsourced: the bound of 1000 is not taken from the SingleTact manual.
+    pub output_scale: u16,
+}
+
+impl SensorConfig {
+    /// Accepted accumulator values.
+    pub const ACCUMULATOR: RangeInclusive<u8> = 1..=32;
+    /// Accepted reference gain values.
+    pub const GAIN: RangeInclusive<u8> = 0..=31;
+    /// Accepted discharge time values.
+    pub const DISCHARGE_TIME: RangeInclusive<u8> = 1..=255;
+    /// Accepted output current values.
+    pub const OUTPUT_CURRENT: RangeInclusive<u8> = 0..=63;
+    /// Accepted output scale values.
+    pub const OUTPUT_SCALE: RangeInclusive<u16> = 1..=1000;
+
+    /// Returns `true` if every field is within its accepted range.
+    pub fn is_valid(&self) -> bool {
+        Self::ACCUMULATOR.contains(&self.accumulator)
+            && Self::GAIN.contains(&self.gain)
+            && Self::DISCHARGE_TIME.contains(&self.discharge_time)
+            && Self::OUTPUT_CURRENT.contains(&self.output_current)
+            && Self::OUTPUT_SCALE.contains(&self.output_scale)
+    }
+}
+
+/// No-load output measured on the host.
+///
+/// Store the bytes from [`Tare::to_bytes`] to restore the tare with
+/// [`Tare::from_bytes`] after a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tare {
+    baseline: u16,
+}
+
+impl Tare {
+    /// Tare at the nominal baseline, used until the sensor is tared.
+    pub(crate) const NOMINAL: Tare = Tare {
+        baseline: NOMINAL_BASELINE,
+    };
+
+    /// Create a tare from a no-load output count, `None` if it is above
+    /// [`MAX_COUNT`].
+    pub const fn new(baseline: u16) -> Option<Self> {
+        if baseline > MAX_COUNT {
+            return None;
+        }
+        Some(Tare { baseline })
+    }
+
+    /// No-load output count.
+    pub const fn baseline(self) -> u16 {
+        self.baseline
+    }
+
+    /// Output count relative to the tare, saturated to the range of `i16`.
+    pub const fn apply(self, output: u16) -> i16 {
+        let relative = output as i32 - self.baseline as i32;
+        if relative > i16::MAX as i32 {
+            i16::MAX
+        } else {
+            relative as i16
+        }
+    }
+
+    /// Serialise the tare.
+    pub const fn to_bytes(self) -> [u8; 2] {
+        self.baseline.to_be_bytes()
+    }
+
+    /// Deserialise a tare stored with [`Tare::to_bytes`], `None` if the
+    /// bytes do not hold a valid baseline.
+    pub const fn from_bytes(bytes: [u8; 2]) -> Option<Self> {
+        Tare::new(u16::from_be_bytes(bytes))
+    }
+}
+
 /// Result of a measurement
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SensorFrameMeasurement {
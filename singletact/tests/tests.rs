@@ -0,0 +1,867 @@
+use embedded_hal_mock::eh1::i2c::{Mock as I2cMock, Transaction as I2cTrans};
+use singletact::{DEFAULT_DEVICE_ADDRESS as DEV_ADDR, Error, SensorConfig, SingleTact};
+
+const CONFIG: SensorConfig = SensorConfig {
+    accumulator: 8,
+    gain: 5,
+    discharge_time: 20,
+    output_current: 12,
+    output_scale: 300,
+};
+
+#[test]
+fn can_create_and_destroy() {
+    let mut i2c = I2cMock::new(&[]);
+    let dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    dev.destroy().done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn can_read_config() {
+    let expectations = [I2cTrans::write_read(
+        DEV_ADDR,
+        vec![0x01, 0x05, 0x07, 0xFF],
+        vec![8, 5, 0x11, 20, 12, 0x01, 0x2C],
+    )];
+    let mut i2c = I2cMock::new(&expectations);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    assert_eq!(dev.read_config().unwrap(), CONFIG);
+    dev.destroy().done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn can_write_config() {
+    let expectations = [
+        I2cTrans::write(DEV_ADDR, vec![0x02, 0x05, 0x02, 8, 5, 0xFF]),
+        I2cTrans::write(DEV_ADDR, vec![0x02, 0x08, 0x04, 20, 12, 0x01, 0x2C, 0xFF]),
+    ];
+    let mut i2c = I2cMock::new(&expectations);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    dev.write_config(&CONFIG).unwrap();
+    dev.destroy().done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn refuses_out_of_range_config() {
+    let mut i2c = I2cMock::new(&[]);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    for config in [
+        SensorConfig {
+            accumulator: 0,
+            ..CONFIG
+        },
+        SensorConfig { gain: 32, ..CONFIG },
+        SensorConfig {
+            output_scale: 1001,
+            ..CONFIG
+        },
+    ] {
+        assert!(!config.is_valid());
+        assert!(matches!(
+            dev.write_config(&config),
+            Err(Error::InvalidInputData)
+        ));
+    }
+    dev.destroy().done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn can_read_calibration_state() {
+    let expectations = [
+        I2cTrans::write_read(DEV_ADDR, vec![0x01, 0x0C, 0x01, 0xFF], vec![1]),
+        I2cTrans::write_read(DEV_ADDR, vec![0x01, 0x0D, 0x01, 0xFF], vec![1]),
+    ];
+    let mut i2c = I2cMock::new(&expectations);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    assert_eq!(dev.get_num_elements().unwrap(), 1);
+    assert!(dev.is_calibrated().unwrap());
+    dev.destroy().done();
+}
+
+#[test]
+fn converts_counts_to_force() {
+    use singletact::{Force, NOMINAL_BASELINE, SensorFrameMeasurement, SensorModel};
+
+    let reading = SensorModel::Range10N.force(NOMINAL_BASELINE, NOMINAL_BASELINE);
+    assert_eq!(reading.force, Force::from_newtons(0.0));
+    assert!(!reading.saturated);
+
+    // Half scale above a measured baseline
+    let frame = SensorFrameMeasurement {
+        index: 0,
+        timestamp: 0,
+        output: 260 + 511 / 2,
+    };
+    let reading = frame.force(SensorModel::Range450N, 260);
+    assert!((reading.force.newtons() - 224.56).abs() < 0.01);
+    assert!(!reading.saturated);
+
+    let reading = SensorModel::Range1N.force(260 + 511, 260);
+    assert_eq!(reading.force.newtons(), 1.0);
+    assert!(reading.saturated);
+    assert!(SensorModel::Range1N.force(1023, 600).saturated);
+
+    assert!((Force::from_newtons(4.5).grams_force() - 458.87).abs() < 0.01);
+    assert!((Force::from_grams_force(1000.0).newtons() - 9.80665).abs() < 1e-4);
+}
+
+fn frame(output: u16) -> Vec<u8> {
+    let [high, low] = output.to_be_bytes();
+    vec![0x00, 0x01, 0x00, 0x02, high, low]
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn can_tare() {
+    use singletact::{SensorModel, Tare};
+
+    let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+    let expectations = [
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(260)),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(262)),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(263)),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(250)),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(262 + 511)),
+    ];
+    let mut i2c = I2cMock::new(&expectations);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    assert!(matches!(dev.tare(0), Err(Error::InvalidInputData)));
+
+    let tare = dev.tare(3).unwrap();
+    assert_eq!(tare.baseline(), 262);
+    assert_eq!(dev.get_tare(), Some(tare));
+    assert_eq!(Tare::from_bytes(tare.to_bytes()), Some(tare));
+
+    assert_eq!(dev.read_tared_output().unwrap(), -12);
+    let reading = dev.read_force(SensorModel::Range10N).unwrap();
+    assert_eq!(reading.force.newtons(), 10.0);
+    assert!(reading.saturated);
+    dev.destroy().done();
+}
+
+#[test]
+fn tare_is_range_checked() {
+    use singletact::{MAX_COUNT, Tare};
+
+    assert!(Tare::new(MAX_COUNT).is_some());
+    assert_eq!(Tare::new(MAX_COUNT + 1), None);
+    assert_eq!(Tare::from_bytes((MAX_COUNT + 1).to_be_bytes()), None);
+
+    let tare = Tare::new(MAX_COUNT).unwrap();
+    assert_eq!(tare.apply(0), -1023);
+    assert_eq!(tare.apply(u16::MAX), i16::MAX);
+    assert_eq!(Tare::new(0).unwrap().apply(40000), i16::MAX);
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn can_write_baseline() {
+    let expectations = [I2cTrans::write(
+        DEV_ADDR,
+        vec![0x02, 0x29, 0x02, 0x01, 0x02, 0xFF],
+    )];
+    let mut i2c = I2cMock::new(&expectations);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    dev.write_baseline(0x0102).unwrap();
+    assert!(matches!(
+        dev.write_baseline(1024),
+        Err(Error::InvalidInputData)
+    ));
+    dev.destroy().done();
+}
+
+#[test]
+fn tracks_frames() {
+    use singletact::{FrameTracker, SensorFrameMeasurement};
+
+    let frame = |index, timestamp| SensorFrameMeasurement {
+        index,
+        timestamp,
+        output: 300,
+    };
+    let mut tracker = FrameTracker::new();
+
+    let sample = tracker.update(frame(0xFFFE, 0xFFF0));
+    assert_eq!((sample.index, sample.timestamp), (0, 0));
+    let sample = tracker.update(frame(0xFFFE, 0xFFF0));
+    assert!(sample.duplicate);
+    assert_eq!(sample.index, 0);
+
+    // Both counters wrap, one frame was missed
+    let sample = tracker.update(frame(0x0000, 0x0010));
+    assert_eq!((sample.index, sample.timestamp), (2, 0x20));
+    assert!(!sample.duplicate);
+    assert_eq!(sample.dropped, 1);
+
+    assert_eq!(tracker.dropped(), 1);
+    assert_eq!(tracker.duplicates(), 1);
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn samples_continuously() {
+    use singletact::Sampler;
+
+    let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+    let expectations = [
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 1, 0, 10, 1, 0]),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 1, 0, 10, 1, 0]),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 2, 0, 20, 1, 1]),
+    ];
+    let mut i2c = I2cMock::new(&expectations);
+    let sampler = Sampler::new(SingleTact::new(&mut i2c, DEV_ADDR));
+
+    let samples: Vec<_> = sampler
+        .take(3)
+        .map(Result::unwrap)
+        .filter(|sample| !sample.duplicate)
+        .map(|sample| (sample.index, sample.timestamp, sample.output))
+        .collect();
+    assert_eq!(samples, [(0, 0, 256), (1, 10, 257)]);
+    i2c.done();
+}
+
+fn info(address: u8, reported: u8) -> Vec<I2cTrans> {
+    vec![
+        I2cTrans::write_read(address, vec![0x01, 0x00, 0x01, 0xFF], vec![reported]),
+        I2cTrans::write_read(address, vec![0x01, 0x01, 0x02, 0xFF], vec![0x12, 0x34]),
+        I2cTrans::write_read(address, vec![0x01, 0x07, 0x01, 0xFF], vec![0x11]),
+        I2cTrans::write_read(address, vec![0x01, 0x29, 0x02, 0xFF], vec![0x00, 0xFF]),
+    ]
+}
+
+fn no_answer(address: u8) -> I2cTrans {
+    use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+
+    I2cTrans::write_read(address, vec![0x01, 0x00, 0x01, 0xFF], vec![0])
+        .with_error(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn can_set_address() {
+    let mut expectations = vec![I2cTrans::write(
+        DEV_ADDR,
+        vec![0x02, 0x00, 0x01, 0x10, 0xFF],
+    )];
+    expectations.extend(info(0x10, 0x10));
+    let mut i2c = I2cMock::new(&expectations);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    assert!(matches!(
+        dev.set_address(0x78),
+        Err(Error::InvalidInputData)
+    ));
+    assert!(matches!(
+        dev.set_address(0x07),
+        Err(Error::InvalidInputData)
+    ));
+    dev.set_address(0x10).unwrap();
+    dev.destroy().done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn set_address_rolls_back() {
+    let mut expectations = vec![
+        I2cTrans::write(DEV_ADDR, vec![0x02, 0x00, 0x01, 0x10, 0xFF]),
+        no_answer(0x10),
+    ];
+    expectations.extend(info(DEV_ADDR, DEV_ADDR));
+    expectations.extend([
+        I2cTrans::write(DEV_ADDR, vec![0x02, 0x00, 0x01, 0x11, 0xFF]),
+        no_answer(0x11),
+        no_answer(DEV_ADDR),
+    ]);
+    let mut i2c = I2cMock::new(&expectations);
+    let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+    assert!(matches!(
+        dev.set_address(0x10),
+        Err(Error::AddressNotChanged)
+    ));
+    assert!(matches!(dev.set_address(0x11), Err(Error::SensorLost)));
+    dev.destroy().done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn provisions_sensors_one_at_a_time() {
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTrans};
+    use singletact::provision::{Assignment, EnablePins, Outcome, Provisioner, Sequential};
+
+    let low = || PinTrans::set(State::Low);
+    let high = || PinTrans::set(State::High);
+    let mut pins = [
+        PinMock::new(&[high(), low(), low(), low()]),
+        PinMock::new(&[low(), high(), low(), low()]),
+        PinMock::new(&[low(), low(), high(), low()]),
+    ];
+
+    // Fresh sensor, sensor provisioned on an earlier run, missing sensor
+    let mut expectations = info(DEV_ADDR, DEV_ADDR);
+    expectations.push(I2cTrans::write(
+        DEV_ADDR,
+        vec![0x02, 0x00, 0x01, 0x10, 0xFF],
+    ));
+    expectations.extend(info(0x10, 0x10));
+    expectations.push(no_answer(DEV_ADDR));
+    expectations.extend(info(0x11, 0x11));
+    expectations.extend([no_answer(DEV_ADDR), no_answer(0x12)]);
+    let mut i2c = I2cMock::new(&expectations);
+
+    let isolate = EnablePins::new(&mut i2c, &mut pins, NoopDelay::new(), 10);
+    let mut provisioner = Provisioner::new(isolate, Sequential(0x10));
+    let report = provisioner.run::<4>().unwrap();
+
+    let sensor = |address| Assignment {
+        serial: 0x1234,
+        address,
+    };
+    assert!(matches!(report.outcomes()[0], Outcome::Assigned(a) if a == sensor(0x10)));
+    assert!(matches!(report.outcomes()[1], Outcome::AlreadyAssigned(a) if a == sensor(0x11)));
+    assert!(matches!(report.outcomes()[2], Outcome::Missing));
+    assert!(matches!(report.outcomes()[3], Outcome::Skipped));
+    assert_eq!(report.assignments().count(), 2);
+    assert!(!report.is_complete());
+    assert_eq!(
+        Assignment::from_bytes(sensor(0x10).to_bytes()),
+        sensor(0x10)
+    );
+
+    pins.iter_mut().for_each(PinMock::done);
+    i2c.done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn refuses_duplicate_planned_addresses() {
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTrans};
+    use singletact::provision::{EnablePins, Outcome, Provisioner};
+
+    let mut pins = [
+        PinMock::new(&[PinTrans::set(State::High), PinTrans::set(State::Low)]),
+        PinMock::new(&[PinTrans::set(State::Low)]),
+    ];
+    let mut expectations = info(DEV_ADDR, DEV_ADDR);
+    expectations.push(I2cTrans::write(
+        DEV_ADDR,
+        vec![0x02, 0x00, 0x01, 0x10, 0xFF],
+    ));
+    expectations.extend(info(0x10, 0x10));
+    let mut i2c = I2cMock::new(&expectations);
+
+    let isolate = EnablePins::new(&mut i2c, &mut pins, NoopDelay::new(), 10);
+    let mut provisioner = Provisioner::new(isolate, [0x10, 0x10]);
+    let report = provisioner.run::<2>().unwrap();
+    assert!(matches!(report.outcomes()[0], Outcome::Assigned(_)));
+    assert!(matches!(report.outcomes()[1], Outcome::DuplicateAddress));
+    assert!(!report.is_complete());
+
+    pins.iter_mut().for_each(PinMock::done);
+    i2c.done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn returns_partial_report_when_isolation_fails() {
+    use embedded_hal_mock::eh1::MockError;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTrans};
+    use singletact::provision::{EnablePins, Outcome, Provisioner, Sequential};
+
+    let low = || PinTrans::set(State::Low);
+    let high = || PinTrans::set(State::High);
+    let mut pins = [
+        PinMock::new(&[high(), low(), low()]),
+        PinMock::new(&[
+            low(),
+            high().with_error(MockError::Io(std::io::ErrorKind::Other)),
+        ]),
+    ];
+    let mut expectations = info(DEV_ADDR, DEV_ADDR);
+    expectations.push(I2cTrans::write(
+        DEV_ADDR,
+        vec![0x02, 0x00, 0x01, 0x10, 0xFF],
+    ));
+    expectations.extend(info(0x10, 0x10));
+    let mut i2c = I2cMock::new(&expectations);
+
+    let isolate = EnablePins::new(&mut i2c, &mut pins, NoopDelay::new(), 10);
+    let mut provisioner = Provisioner::new(isolate, Sequential(0x10));
+    let Err((report, MockError::Io(std::io::ErrorKind::Other))) = provisioner.run::<2>() else {
+        panic!("isolating the second sensor should fail");
+    };
+    assert!(matches!(report.outcomes()[0], Outcome::Assigned(_)));
+    assert!(matches!(report.outcomes()[1], Outcome::Skipped));
+
+    pins.iter_mut().for_each(PinMock::done);
+    i2c.done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn address_plans() {
+    use singletact::provision::{AddressPlan, Sequential};
+
+    assert_eq!(Sequential(0x10).address(3), Some(0x13));
+    assert_eq!(Sequential(0xFF).address(1), None);
+    assert_eq!([0x20, 0x30].address(1), Some(0x30));
+    assert_eq!([0x20, 0x30][..].address(2), None);
+}
+
+#[cfg(all(feature = "pca9548", not(feature = "async")))]
+#[test]
+fn provisions_sensors_behind_multiplexer() {
+    use pca9548::{Channel, PCA9548};
+    use singletact::provision::{MuxChannels, Provisioner};
+
+    let mut expectations = vec![I2cTrans::write(0x70, vec![0b0000_0100])];
+    expectations.extend(info(DEV_ADDR, DEV_ADDR));
+    expectations.push(I2cTrans::write(
+        DEV_ADDR,
+        vec![0x02, 0x00, 0x01, 0x20, 0xFF],
+    ));
+    expectations.extend(info(0x20, 0x20));
+    expectations.push(I2cTrans::write(0x70, vec![0b0000_0000]));
+    let mut i2c = I2cMock::new(&expectations);
+
+    let mut mux = PCA9548::new(&mut i2c);
+    let channels = [Channel::new(2).unwrap()];
+    let mut provisioner = Provisioner::new(MuxChannels::new(&mut mux, &channels), [0x20]);
+    let report = provisioner.run::<1>().unwrap();
+    assert!(report.is_complete());
+
+    mux.destroy().done();
+}
+
+#[test]
+fn moving_average_covers_time_window() {
+    use singletact::filter::{Filter, MovingAverage};
+
+    let mut filter = MovingAverage::<4>::new(100);
+    assert_eq!(filter.update(0, 10.0), 10.0);
+    assert_eq!(filter.update(50, 20.0), 15.0);
+    // The first reading fell out of the window
+    assert_eq!(filter.update(100, 30.0), 25.0);
+    // Duplicate frame replaces the reading
+    assert_eq!(filter.update(100, 40.0), 30.0);
+    // Timestamps restarted
+    assert_eq!(filter.update(50, 5.0), 5.0);
+
+    let mut filter = MovingAverage::<2>::new(u64::MAX);
+    filter.update(0, 1.0);
+    filter.update(1, 2.0);
+    assert_eq!(filter.update(2, 3.0), 2.5);
+}
+
+#[test]
+fn median_rejects_spikes() {
+    use singletact::filter::{Filter, Median};
+
+    let mut filter = Median::<3>::new(u64::MAX);
+    assert_eq!(filter.update(0, 10.0), 10.0);
+    assert_eq!(filter.update(1, 1000.0), 505.0);
+    assert_eq!(filter.update(2, 12.0), 12.0);
+    assert_eq!(filter.update(3, 11.0), 12.0);
+    filter.reset();
+    assert_eq!(filter.update(4, 1.0), 1.0);
+}
+
+#[test]
+fn low_pass_follows_sample_interval() {
+    use singletact::filter::{Filter, LowPass};
+
+    let mut filter = LowPass::new(10);
+    assert_eq!(filter.update(0, 0.0), 0.0);
+    assert_eq!(filter.update(10, 100.0), 50.0);
+    assert_eq!(filter.update(10, 0.0), 50.0);
+    // A longer gap moves the output further
+    assert_eq!(filter.update(40, 100.0), 87.5);
+}
+
+#[test]
+fn one_euro_adapts_to_rate_of_change() {
+    use singletact::filter::{Filter, OneEuro};
+
+    let mut steady = OneEuro::new(1.0, 0.0);
+    steady.update(0, 0.0);
+    assert!((steady.update(10_000, 1.0) - 0.8627).abs() < 1e-4);
+
+    let mut steady = OneEuro::new(1.0, 0.0);
+    let mut adaptive = OneEuro::new(1.0, 1.0);
+    steady.update(0, 0.0);
+    adaptive.update(0, 0.0);
+    assert!(steady.update(100, 100.0) < 10.0);
+    assert!(adaptive.update(100, 100.0) > 90.0);
+}
+
+#[test]
+fn filters_chain() {
+    use singletact::Sample;
+    use singletact::filter::{Filter, Median, MovingAverage};
+
+    let sample = |timestamp, output| Sample {
+        index: timestamp,
+        timestamp,
+        output,
+        duplicate: false,
+        dropped: 0,
+    };
+    let mut filter = Median::<3>::new(u64::MAX).chain(MovingAverage::<2>::new(u64::MAX));
+    assert_eq!(filter.update_sample(&sample(0, 10)), 10.0);
+    assert_eq!(filter.update_sample(&sample(1, 1000)), 257.5);
+    assert_eq!(filter.update_sample(&sample(2, 12)), 258.5);
+
+    filter.reset();
+    assert_eq!(filter.update_sample(&sample(3, 20)), 20.0);
+    let (_median, mut average) = filter.into_inner();
+    assert_eq!(average.update(4, 30.0), 25.0);
+}
+
+#[test]
+fn detects_events_with_hysteresis_and_dwell() {
+    use singletact::event::{Band, Event, EventDetector, EventKind::*};
+
+    let mut detector = EventDetector::new(Band::counts(50, 30).unwrap())
+        .with_threshold(Band::counts(200, 150).unwrap())
+        .with_dwell(10)
+        .with_sustain(100);
+    let mut events = Vec::new();
+    for (timestamp, output) in [
+        (0, 255.0),
+        (10, 310.0),
+        // Bounce shorter than the dwell time
+        (15, 290.0),
+        (20, 310.0),
+        (30, 470.0),
+        (40, 470.0),
+        // Inside the threshold hysteresis band
+        (50, 420.0),
+        (120, 420.0),
+        (130, 280.0),
+        (140, 280.0),
+    ] {
+        events.extend(detector.update(timestamp, output));
+    }
+
+    let event = |kind, timestamp| Event { kind, timestamp };
+    assert_eq!(
+        events,
+        [
+            event(ContactMade, 20),
+            event(ThresholdExceeded, 30),
+            event(SustainedLoad, 120),
+            event(ThresholdCleared, 130),
+            event(ContactReleased, 130),
+        ]
+    );
+    assert!(!detector.in_contact());
+    assert!(!detector.above_threshold());
+}
+
+#[test]
+fn event_bands() {
+    use singletact::event::Band;
+    use singletact::{Force, SensorModel};
+
+    let band = Band::force(
+        SensorModel::Range10N,
+        Force::from_newtons(6.0),
+        Force::from_newtons(5.0),
+    )
+    .unwrap();
+    assert!((band.rising() - 306.6).abs() < 1e-3);
+    assert_eq!(band.falling(), 255.5);
+    let band = Band::counts(50, 50).unwrap();
+    assert_eq!((band.rising(), band.falling()), (50.0, 50.0));
+}
+
+#[test]
+fn rejects_inverted_bands() {
+    use singletact::event::Band;
+    use singletact::{Force, SensorModel};
+
+    assert_eq!(Band::counts(50, 80), None);
+    assert_eq!(
+        Band::force(
+            SensorModel::Range10N,
+            Force::from_newtons(5.0),
+            Force::from_newtons(6.0),
+        ),
+        None
+    );
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn streams_events() {
+    use singletact::event::{Band, Event, EventDetector, EventKind, EventStream};
+
+    let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+    let expectations = [
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 1, 0, 10, 0, 255]),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 2, 0, 20, 1, 100]),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 2, 0, 20, 1, 100]),
+        I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 3, 0, 30, 0, 255]),
+    ];
+    let mut i2c = I2cMock::new(&expectations);
+    let detector = EventDetector::new(Band::counts(50, 30).unwrap());
+    let mut events = EventStream::new(SingleTact::new(&mut i2c, DEV_ADDR), detector);
+
+    let made = events.next().unwrap().unwrap();
+    assert_eq!(
+        made,
+        Event {
+            kind: EventKind::ContactMade,
+            timestamp: 10
+        }
+    );
+    // The duplicate frame is skipped
+    let released = events.next().unwrap().unwrap();
+    assert_eq!(released.kind, EventKind::ContactReleased);
+    assert_eq!(released.timestamp, 20);
+    events.release().destroy().done();
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn sweeps_sensor_array() {
+    use core::cell::{Cell, RefCell};
+    use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+    use embedded_hal_bus::i2c::RefCellDevice;
+    use singletact::{SensorArray, SensorFrameMeasurement};
+
+    let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+    let lost = || {
+        I2cTrans::write_read(0x11, read_frame.clone(), vec![0; 6])
+            .with_error(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+    };
+    let expectations = [
+        I2cTrans::write_read(0x10, read_frame.clone(), vec![0, 1, 0, 10, 1, 0]),
+        lost(),
+        I2cTrans::write_read(0x12, read_frame.clone(), vec![0, 1, 0, 12, 1, 2]),
+        I2cTrans::write_read(0x10, read_frame.clone(), vec![0, 2, 0, 20, 1, 0]),
+        lost(),
+        I2cTrans::write_read(0x12, read_frame.clone(), vec![0, 2, 0, 22, 1, 2]),
+    ];
+    let i2c = RefCell::new(I2cMock::new(&expectations));
+    let sensors =
+        [0x10, 0x11, 0x12].map(|address| SingleTact::new(RefCellDevice::new(&i2c), address));
+
+    let time = Cell::new(0);
+    let mut array = SensorArray::new(sensors, || {
+        time.set(time.get() + 1000);
+        time.get()
+    });
+    let frame = array.sweep();
+    assert_eq!(frame.timestamp, 1000);
+    assert!(!frame.is_complete());
+    assert!(matches!(frame.readings[1], Err(Error::I2C(_))));
+
+    let frame = array.sweep();
+    assert_eq!(frame.timestamp, 2000);
+    let frames: Vec<_> = frame
+        .frames()
+        .map(|(position, frame)| (position, frame.output))
+        .collect();
+    assert_eq!(frames, [(0, 256), (2, 258)]);
+
+    let health = array.health();
+    assert!(health[0].is_ok());
+    assert_eq!(health[1].consecutive_errors, 2);
+    assert_eq!(health[1].last_good, None);
+    assert!(!health[1].is_ok());
+    assert_eq!(
+        health[2].last_good,
+        Some(SensorFrameMeasurement {
+            index: 2,
+            timestamp: 22,
+            output: 258
+        })
+    );
+    assert_eq!(health[2].last_good_timestamp, Some(2000));
+
+    i2c.into_inner().done();
+}
+
+#[cfg(feature = "async")]
+mod test_async {
+    use super::*;
+    use core::pin::pin;
+    use embassy_futures::block_on;
+    use futures_util::StreamExt;
+
+    #[test]
+    fn can_read_and_write_config() {
+        let expectations = [
+            I2cTrans::write_read(
+                DEV_ADDR,
+                vec![0x01, 0x05, 0x07, 0xFF],
+                vec![8, 5, 0x11, 20, 12, 0x01, 0x2C],
+            ),
+            I2cTrans::write(DEV_ADDR, vec![0x02, 0x05, 0x02, 8, 5, 0xFF]),
+            I2cTrans::write(DEV_ADDR, vec![0x02, 0x08, 0x04, 20, 12, 0x01, 0x2C, 0xFF]),
+        ];
+        let mut i2c = I2cMock::new(&expectations);
+        let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+        block_on(async {
+            assert_eq!(dev.read_config().await.unwrap(), CONFIG);
+            dev.write_config(&CONFIG).await.unwrap();
+            // Refused before anything is written
+            let config = SensorConfig { gain: 32, ..CONFIG };
+            assert!(matches!(
+                dev.write_config(&config).await,
+                Err(Error::InvalidInputData)
+            ));
+        });
+        dev.destroy().done();
+    }
+
+    #[test]
+    fn can_tare_and_write_baseline() {
+        let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+        let expectations = [
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(260)),
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(264)),
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), frame(250)),
+            I2cTrans::write(DEV_ADDR, vec![0x02, 0x29, 0x02, 0x01, 0x06, 0xFF]),
+        ];
+        let mut i2c = I2cMock::new(&expectations);
+        let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+        block_on(async {
+            let tare = dev.tare(2).await.unwrap();
+            assert_eq!(tare.baseline(), 262);
+            assert_eq!(dev.read_tared_output().await.unwrap(), -12);
+            dev.write_baseline(tare.baseline()).await.unwrap();
+            assert!(matches!(
+                dev.write_baseline(1024).await,
+                Err(Error::InvalidInputData)
+            ));
+        });
+        dev.destroy().done();
+    }
+
+    #[test]
+    fn set_address_rolls_back() {
+        let mut expectations = vec![
+            I2cTrans::write(DEV_ADDR, vec![0x02, 0x00, 0x01, 0x10, 0xFF]),
+            no_answer(0x10),
+        ];
+        expectations.extend(info(DEV_ADDR, DEV_ADDR));
+        expectations.extend([
+            I2cTrans::write(DEV_ADDR, vec![0x02, 0x00, 0x01, 0x11, 0xFF]),
+            no_answer(0x11),
+            no_answer(DEV_ADDR),
+        ]);
+        let mut i2c = I2cMock::new(&expectations);
+        let mut dev = SingleTact::new(&mut i2c, DEV_ADDR);
+        block_on(async {
+            assert!(matches!(
+                dev.set_address(0x10).await,
+                Err(Error::AddressNotChanged)
+            ));
+            assert!(matches!(
+                dev.set_address(0x11).await,
+                Err(Error::SensorLost)
+            ));
+        });
+        dev.destroy().done();
+    }
+
+    #[test]
+    fn streams_samples() {
+        use singletact::Sampler;
+
+        let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+        let expectations = [
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 1, 0, 10, 1, 0]),
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 1, 0, 10, 1, 0]),
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 3, 0, 30, 1, 1]),
+        ];
+        let mut i2c = I2cMock::new(&expectations);
+        block_on(async {
+            let mut sampler = Sampler::new(SingleTact::new(&mut i2c, DEV_ADDR));
+            let sample = sampler.sample().await.unwrap();
+            assert_eq!((sample.index, sample.timestamp), (0, 0));
+
+            let mut samples = pin!(sampler.into_stream());
+            let sample = samples.next().await.unwrap().unwrap();
+            assert!(sample.duplicate);
+            let sample = samples.next().await.unwrap().unwrap();
+            assert_eq!(
+                (sample.index, sample.timestamp, sample.output),
+                (2, 20, 257)
+            );
+            assert_eq!(sample.dropped, 1);
+        });
+        i2c.done();
+    }
+
+    #[test]
+    fn streams_events() {
+        use singletact::event::{Band, Event, EventDetector, EventKind, EventStream};
+
+        let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+        let expectations = [
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 1, 0, 10, 0, 255]),
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 2, 0, 20, 1, 100]),
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 2, 0, 20, 1, 100]),
+            I2cTrans::write_read(DEV_ADDR, read_frame.clone(), vec![0, 3, 0, 30, 0, 255]),
+        ];
+        let mut i2c = I2cMock::new(&expectations);
+        let detector = EventDetector::new(Band::counts(50, 30).unwrap());
+        block_on(async {
+            let mut events = EventStream::new(SingleTact::new(&mut i2c, DEV_ADDR), detector);
+            let made = events.next_event().await.unwrap();
+            assert_eq!(
+                made,
+                Event {
+                    kind: EventKind::ContactMade,
+                    timestamp: 10
+                }
+            );
+
+            // The duplicate frame is skipped
+            let mut events = pin!(events.into_stream());
+            let released = events.next().await.unwrap().unwrap();
+            assert_eq!(released.kind, EventKind::ContactReleased);
+            assert_eq!(released.timestamp, 20);
+        });
+        i2c.done();
+    }
+
+    #[test]
+    fn sweeps_sensor_array() {
+        use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+        use singletact::SensorArray;
+
+        let read_frame = vec![0x01, 0x84, 0x06, 0xFF];
+        let expectations = [
+            I2cTrans::write_read(0x10, read_frame.clone(), vec![0, 1, 0, 10, 1, 0]),
+            I2cTrans::write_read(0x11, read_frame.clone(), vec![0; 6])
+                .with_error(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+            I2cTrans::write_read(0x12, read_frame.clone(), vec![0, 1, 0, 12, 1, 2]),
+        ];
+        // Clones of the mock share its expectations
+        let mut i2c = I2cMock::new(&expectations);
+        let sensors = [0x10, 0x11, 0x12].map(|address| SingleTact::new(i2c.clone(), address));
+
+        let mut array = SensorArray::new(sensors, || 1000);
+        let frame = block_on(array.sweep());
+        assert_eq!(frame.timestamp, 1000);
+        assert!(matches!(frame.readings[1], Err(Error::I2C(_))));
+        let frames: Vec<_> = frame
+            .frames()
+            .map(|(position, frame)| (position, frame.output))
+            .collect();
+        assert_eq!(frames, [(0, 256), (2, 258)]);
+        assert_eq!(array.health()[1].consecutive_errors, 1);
+
+        i2c.done();
+    }
+}